@@ -1,8 +1,13 @@
 //! Configuration for `HttpClient`s.
 
 use std::fmt::Debug;
+use std::sync::Arc;
 use std::time::Duration;
 
+use http_types::url::Url;
+
+use crate::{FetchOptions, KeyPins, NoProxy, Proxy, Resolve, TlsSettings};
+
 /// Configuration for `HttpClient`s.
 #[non_exhaustive]
 #[derive(Clone)]
@@ -19,10 +24,39 @@ pub struct Config {
     ///
     /// Note: Does nothing on `wasm_client`.
     pub tcp_no_delay: bool,
-    /// Connection timeout duration.
+    /// Timeout for sending a request and receiving the response head.
+    ///
+    /// `response_header_timeout` takes precedence when set.
     ///
     /// Default: `Some(Duration::from_secs(60))`.
     pub timeout: Option<Duration>,
+    /// Timeout for opening a connection, including any tunnel through a proxy.
+    ///
+    /// Default: `None`.
+    ///
+    /// Note: Does nothing on `wasm_client`.
+    pub connect_timeout: Option<Duration>,
+    /// Timeout for the TLS handshake of `https` connections.
+    ///
+    /// Default: `None`.
+    ///
+    /// Note: Does nothing on `wasm_client`. On `curl_client`, which times connecting and the
+    /// handshake together, it is added to `connect_timeout`.
+    pub tls_handshake_timeout: Option<Duration>,
+    /// Timeout for receiving the response head once the request is sent.
+    ///
+    /// Default: `None`.
+    ///
+    /// Note: Includes the time taken to connect on every backend but `h1_client`.
+    pub response_header_timeout: Option<Duration>,
+    /// Timeout for each wait on the response body to make progress.
+    ///
+    /// Default: `None`.
+    pub read_idle_timeout: Option<Duration>,
+    /// Timeout for the whole request, from sending it to reading the end of the response body.
+    ///
+    /// Default: `None`.
+    pub total_timeout: Option<Duration>,
     /// Maximum number of simultaneous connections that this client is allowed to keep open to individual hosts at one time.
     ///
     /// Default: `50`.
@@ -34,6 +68,100 @@ pub struct Config {
     /// - `hyper_client`: No effect. Hyper does not support such an option.
     /// - `wasm_client`: No effect. Web browsers do not support such an option.
     pub max_connections_per_host: usize,
+    /// How long a pooled connection may sit idle before it is closed.
+    ///
+    /// Default: `Some(Duration::from_secs(90))`.
+    ///
+    /// Note: Does nothing on `wasm_client`.
+    pub pool_idle_timeout: Option<Duration>,
+    /// How long a pooled connection may be used for, from when it was opened, before it is
+    /// closed.
+    ///
+    /// Default: `None`.
+    ///
+    /// Note: Only `h1_client` supports this.
+    pub pool_max_lifetime: Option<Duration>,
+    /// How many requests may be sent over a pooled connection before it is closed.
+    ///
+    /// Default: `None`.
+    ///
+    /// Note: Only `h1_client` supports this.
+    pub max_requests_per_connection: Option<usize>,
+    /// The proxy which requests are sent through, unless `http_proxy` or `https_proxy` applies.
+    ///
+    /// Default: `None`.
+    ///
+    /// Note: Does nothing on `wasm_client`. Where no proxy applies, `curl_client` falls back to
+    /// curl's own proxy environment variables, except for hosts listed in `no_proxy`.
+    pub proxy: Option<Proxy>,
+    /// The proxy which `http` requests are sent through, instead of `proxy`.
+    ///
+    /// Default: `None`.
+    ///
+    /// Note: Does nothing on `wasm_client`.
+    pub http_proxy: Option<Proxy>,
+    /// The proxy which `https` requests are sent through, instead of `proxy`.
+    ///
+    /// Default: `None`.
+    ///
+    /// Note: Does nothing on `wasm_client`.
+    pub https_proxy: Option<Proxy>,
+    /// Hosts which requests are sent to directly, even when a proxy is set.
+    ///
+    /// Default: `None`.
+    ///
+    /// Note: Does nothing on `wasm_client`.
+    pub no_proxy: Option<NoProxy>,
+    /// The resolver which hostnames are resolved with.
+    ///
+    /// Default: `None`, which uses `SystemResolver`.
+    ///
+    /// Note: Does nothing on `curl_client` and `wasm_client`, which resolve hostnames themselves.
+    pub resolver: Option<Arc<dyn Resolve>>,
+    /// How long to wait on a connection attempt before also trying the next address of a host,
+    /// alternating between IPv6 and IPv4 addresses as in RFC 8305 ("Happy Eyeballs").
+    ///
+    /// With `None`, each address is only tried once the last one has failed.
+    ///
+    /// Default: `Some(Duration::from_millis(250))`.
+    ///
+    /// Note: `hyper_client` races IPv6 addresses against IPv4 ones, but tries addresses of the
+    /// same family one after another. Does nothing on `curl_client` and `wasm_client`.
+    pub happy_eyeballs_delay: Option<Duration>,
+    /// Transparently decompress response bodies.
+    ///
+    /// Requests which do not set their own `Accept-Encoding` header advertise `gzip`, `deflate`,
+    /// `br` and `zstd`, and response bodies in those encodings are decoded as they are read. The
+    /// `Content-Encoding` and `Content-Length` headers are removed from decoded responses.
+    ///
+    /// Default: `false`.
+    ///
+    /// Note: Does nothing on `wasm_client`, where the browser always decompresses responses.
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "decompression")))]
+    #[cfg(feature = "decompression")]
+    pub decompress: bool,
+    /// The client identity, root certificates and protocol versions of `https` connections.
+    ///
+    /// Default: `TlsSettings::new()`, which trusts the built-in roots.
+    ///
+    /// Note: On `h1_client`, `tls_config` takes precedence when set. Does nothing on
+    /// `wasm_client`, where the browser handles TLS.
+    pub tls: TlsSettings,
+    /// The public keys which the certificates of hosts must have.
+    ///
+    /// Default: `None`.
+    ///
+    /// Note: Only `h1_client` and `hyper_client` check pins, with `rustls` after the certificate
+    /// verifier of `tls_config` when set. `curl_client` refuses configs with them, and
+    /// `wasm_client` ignores them.
+    pub key_pins: Option<KeyPins>,
+    /// The options of each fetch, such as its CORS mode and credentials.
+    ///
+    /// Default: `FetchOptions::new()`, which leaves them up to the browser.
+    ///
+    /// Note: Only `wasm_client` uses them. A `FetchOptions` in the extensions of a request
+    /// replaces these for that request.
+    pub fetch: FetchOptions,
     /// TLS Configuration (Rustls)
     #[cfg_attr(feature = "docs", doc(cfg(feature = "h1_client")))]
     #[cfg(all(feature = "h1_client", feature = "rustls"))]
@@ -51,7 +179,32 @@ impl Debug for Config {
             .field("http_keep_alive", &self.http_keep_alive)
             .field("tcp_no_delay", &self.tcp_no_delay)
             .field("timeout", &self.timeout)
-            .field("max_connections_per_host", &self.max_connections_per_host);
+            .field("connect_timeout", &self.connect_timeout)
+            .field("tls_handshake_timeout", &self.tls_handshake_timeout)
+            .field("response_header_timeout", &self.response_header_timeout)
+            .field("read_idle_timeout", &self.read_idle_timeout)
+            .field("total_timeout", &self.total_timeout)
+            .field("max_connections_per_host", &self.max_connections_per_host)
+            .field("pool_idle_timeout", &self.pool_idle_timeout)
+            .field("pool_max_lifetime", &self.pool_max_lifetime)
+            .field(
+                "max_requests_per_connection",
+                &self.max_requests_per_connection,
+            )
+            .field("proxy", &self.proxy)
+            .field("http_proxy", &self.http_proxy)
+            .field("https_proxy", &self.https_proxy)
+            .field("no_proxy", &self.no_proxy)
+            .field("resolver", &self.resolver)
+            .field("happy_eyeballs_delay", &self.happy_eyeballs_delay);
+
+        #[cfg(feature = "decompression")]
+        dbg_struct.field("decompress", &self.decompress);
+
+        dbg_struct
+            .field("tls", &self.tls)
+            .field("key_pins", &self.key_pins)
+            .field("fetch", &self.fetch);
 
         #[cfg(all(feature = "h1_client", feature = "rustls"))]
         {
@@ -77,13 +230,100 @@ impl Config {
             http_keep_alive: true,
             tcp_no_delay: false,
             timeout: Some(Duration::from_secs(60)),
+            connect_timeout: None,
+            tls_handshake_timeout: None,
+            response_header_timeout: None,
+            read_idle_timeout: None,
+            total_timeout: None,
             max_connections_per_host: 50,
+            pool_idle_timeout: Some(Duration::from_secs(90)),
+            pool_max_lifetime: None,
+            max_requests_per_connection: None,
+            proxy: None,
+            http_proxy: None,
+            https_proxy: None,
+            no_proxy: None,
+            resolver: None,
+            happy_eyeballs_delay: Some(Duration::from_millis(250)),
+            #[cfg(feature = "decompression")]
+            decompress: false,
+            tls: TlsSettings::new(),
+            key_pins: None,
+            fetch: FetchOptions::new(),
             #[cfg(all(feature = "h1_client", any(feature = "rustls", feature = "native-tls")))]
             tls_config: None,
         }
     }
 }
 
+impl Config {
+    /// Construct a new config, with the proxies set in the environment.
+    ///
+    /// The `http_proxy`, `https_proxy`, `all_proxy` and `no_proxy` environment variables set
+    /// `http_proxy`, `https_proxy`, `proxy` and `no_proxy` respectively. The lowercase name of each
+    /// variable takes precedence over the uppercase one. Proxy URLs without a scheme are taken to
+    /// be `http`, and invalid ones are ignored.
+    ///
+    /// `HTTP_PROXY` is ignored when `REQUEST_METHOD` is set, as in CGI scripts any client may set
+    /// it with a `Proxy` request header.
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let is_cgi = var("REQUEST_METHOD").is_some();
+        let get = |name: &str| {
+            let upper = name.to_ascii_uppercase();
+            let value = match var(name) {
+                Some(value) => Some(value),
+                None if upper == "HTTP_PROXY" && is_cgi => None,
+                None => var(&upper),
+            };
+            value.filter(|value| !value.trim().is_empty())
+        };
+        let proxy = |name: &str| {
+            let value = get(name)?;
+            let value = value.trim();
+            let url = if value.contains("://") {
+                value.to_string()
+            } else {
+                format!("http://{}", value)
+            };
+            match Proxy::new(&url) {
+                Ok(proxy) => Some(proxy),
+                Err(e) => {
+                    log::warn!("ignoring {}: {}", name, e);
+                    None
+                }
+            }
+        };
+
+        Self {
+            proxy: proxy("all_proxy"),
+            http_proxy: proxy("http_proxy"),
+            https_proxy: proxy("https_proxy"),
+            no_proxy: get("no_proxy").map(|list| NoProxy::new(&list)),
+            ..Self::new()
+        }
+    }
+
+    /// The proxy which a request to `url` is sent through, if any.
+    pub fn proxy_for(&self, url: &Url) -> Option<&Proxy> {
+        if let Some(no_proxy) = &self.no_proxy {
+            if no_proxy.matches(url) {
+                return None;
+            }
+        }
+
+        let proxy = match url.scheme() {
+            "http" => self.http_proxy.as_ref(),
+            "https" => self.https_proxy.as_ref(),
+            _ => None,
+        };
+        proxy.or(self.proxy.as_ref())
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self::new()
@@ -103,18 +343,134 @@ impl Config {
         self
     }
 
-    /// Set connection timeout duration.
+    /// Set timeout for sending a request and receiving the response head.
     pub fn set_timeout(mut self, timeout: Option<Duration>) -> Self {
         self.timeout = timeout;
         self
     }
 
+    /// Set timeout for opening a connection.
+    pub fn set_connect_timeout(mut self, connect_timeout: Option<Duration>) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Set timeout for the TLS handshake.
+    pub fn set_tls_handshake_timeout(mut self, tls_handshake_timeout: Option<Duration>) -> Self {
+        self.tls_handshake_timeout = tls_handshake_timeout;
+        self
+    }
+
+    /// Set timeout for receiving the response head once the request is sent.
+    pub fn set_response_header_timeout(
+        mut self,
+        response_header_timeout: Option<Duration>,
+    ) -> Self {
+        self.response_header_timeout = response_header_timeout;
+        self
+    }
+
+    /// Set timeout for each wait on the response body to make progress.
+    pub fn set_read_idle_timeout(mut self, read_idle_timeout: Option<Duration>) -> Self {
+        self.read_idle_timeout = read_idle_timeout;
+        self
+    }
+
+    /// Set timeout for the whole request, including reading the response body.
+    pub fn set_total_timeout(mut self, total_timeout: Option<Duration>) -> Self {
+        self.total_timeout = total_timeout;
+        self
+    }
+
     /// Set the maximum number of simultaneous connections that this client is allowed to keep open to individual hosts at one time.
     pub fn set_max_connections_per_host(mut self, max_connections_per_host: usize) -> Self {
         self.max_connections_per_host = max_connections_per_host;
         self
     }
 
+    /// Set how long a pooled connection may sit idle before it is closed.
+    pub fn set_pool_idle_timeout(mut self, pool_idle_timeout: Option<Duration>) -> Self {
+        self.pool_idle_timeout = pool_idle_timeout;
+        self
+    }
+
+    /// Set how long a pooled connection may be used for before it is closed.
+    pub fn set_pool_max_lifetime(mut self, pool_max_lifetime: Option<Duration>) -> Self {
+        self.pool_max_lifetime = pool_max_lifetime;
+        self
+    }
+
+    /// Set how many requests may be sent over a pooled connection before it is closed.
+    pub fn set_max_requests_per_connection(
+        mut self,
+        max_requests_per_connection: Option<usize>,
+    ) -> Self {
+        self.max_requests_per_connection = max_requests_per_connection;
+        self
+    }
+
+    /// Set the proxy which requests are sent through, unless a more specific proxy applies.
+    pub fn set_proxy(mut self, proxy: Option<Proxy>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Set the proxy which `http` requests are sent through.
+    pub fn set_http_proxy(mut self, http_proxy: Option<Proxy>) -> Self {
+        self.http_proxy = http_proxy;
+        self
+    }
+
+    /// Set the proxy which `https` requests are sent through.
+    pub fn set_https_proxy(mut self, https_proxy: Option<Proxy>) -> Self {
+        self.https_proxy = https_proxy;
+        self
+    }
+
+    /// Set the hosts which requests are sent to directly, even when a proxy is set.
+    pub fn set_no_proxy(mut self, no_proxy: Option<NoProxy>) -> Self {
+        self.no_proxy = no_proxy;
+        self
+    }
+
+    /// Set the resolver which hostnames are resolved with.
+    pub fn set_resolver(mut self, resolver: Option<Arc<dyn Resolve>>) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    /// Set how long to wait on a connection attempt before also trying the next address.
+    pub fn set_happy_eyeballs_delay(mut self, happy_eyeballs_delay: Option<Duration>) -> Self {
+        self.happy_eyeballs_delay = happy_eyeballs_delay;
+        self
+    }
+
+    /// Set whether response bodies are transparently decompressed.
+    #[cfg_attr(feature = "docs", doc(cfg(feature = "decompression")))]
+    #[cfg(feature = "decompression")]
+    pub fn set_decompress(mut self, decompress: bool) -> Self {
+        self.decompress = decompress;
+        self
+    }
+
+    /// Set the client identity, root certificates and protocol versions of `https` connections.
+    pub fn set_tls(mut self, tls: TlsSettings) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Set the public keys which the certificates of hosts must have.
+    pub fn set_key_pins(mut self, key_pins: Option<KeyPins>) -> Self {
+        self.key_pins = key_pins;
+        self
+    }
+
+    /// Set the options of each fetch made by `wasm_client`.
+    pub fn set_fetch(mut self, fetch: FetchOptions) -> Self {
+        self.fetch = fetch;
+        self
+    }
+
     /// Set TLS Configuration (Rustls)
     #[cfg_attr(feature = "docs", doc(cfg(feature = "h1_client")))]
     #[cfg(all(feature = "h1_client", feature = "rustls"))]
@@ -136,3 +492,62 @@ impl Config {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn from_vars(vars: &[(&str, &str)]) -> Config {
+        let vars: HashMap<_, _> = vars.iter().cloned().collect();
+        Config::from_vars(|name| vars.get(name).map(|value| value.to_string()))
+    }
+
+    fn proxy_for(config: &Config, url: &str) -> Option<String> {
+        config
+            .proxy_for(&Url::parse(url).unwrap())
+            .map(|proxy| proxy.url().to_string())
+    }
+
+    #[test]
+    fn reads_proxies_from_env() {
+        let config = from_vars(&[
+            ("HTTP_PROXY", "http-proxy.example:3128"),
+            ("https_proxy", "http://https-proxy.example:3128"),
+            ("HTTPS_PROXY", "http://ignored.example:3128"),
+            ("ALL_PROXY", "socks5h://all-proxy.example"),
+            ("no_proxy", "localhost,.internal.example,10.0.0.0/8"),
+        ]);
+
+        assert_eq!(
+            proxy_for(&config, "http://example.com/").as_deref(),
+            Some("http://http-proxy.example:3128/")
+        );
+        assert_eq!(
+            proxy_for(&config, "https://example.com/").as_deref(),
+            Some("http://https-proxy.example:3128/")
+        );
+        assert_eq!(proxy_for(&config, "http://localhost:8080/"), None);
+        assert_eq!(proxy_for(&config, "https://api.internal.example/"), None);
+        assert_eq!(proxy_for(&config, "http://10.1.1.1/"), None);
+
+        let config = from_vars(&[("ALL_PROXY", "socks5h://all-proxy.example")]);
+        assert_eq!(
+            proxy_for(&config, "https://example.com/").as_deref(),
+            Some("socks5h://all-proxy.example")
+        );
+    }
+
+    #[test]
+    fn ignores_http_proxy_in_cgi() {
+        let config = from_vars(&[("HTTP_PROXY", "evil.example"), ("REQUEST_METHOD", "GET")]);
+        assert!(config.http_proxy.is_none());
+
+        let config = from_vars(&[("http_proxy", "proxy.example"), ("REQUEST_METHOD", "GET")]);
+        assert!(config.http_proxy.is_some());
+
+        let config = from_vars(&[("http_proxy", "ftp://proxy.example"), ("no_proxy", " ")]);
+        assert!(config.http_proxy.is_none());
+        assert!(config.no_proxy.is_none());
+    }
+}
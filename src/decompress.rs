@@ -0,0 +1,133 @@
+//! Transparent decompression of response bodies, shared by the native backends.
+
+use async_compression::futures::bufread::{BrotliDecoder, GzipDecoder, ZlibDecoder, ZstdDecoder};
+use futures_util::io::{AsyncBufRead, BufReader};
+use http_types::headers::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH};
+use http_types::{Method, StatusCode};
+
+use crate::{Body, Request, Response};
+
+/// The `Accept-Encoding` header sent when the request does not set its own.
+const ACCEPT_ENCODING_VALUE: &str = "gzip, deflate, br, zstd";
+
+/// Advertise the supported encodings, unless the request already chose its own.
+pub(crate) fn prepare(req: &mut Request) {
+    if req.header(ACCEPT_ENCODING).is_none() {
+        req.insert_header(ACCEPT_ENCODING, ACCEPT_ENCODING_VALUE);
+    }
+}
+
+/// Decode the body of `res` according to its `Content-Encoding` header.
+///
+/// The `Content-Encoding` and `Content-Length` headers are removed from decoded responses, as they
+/// describe the encoded body. Responses with an unknown encoding are returned untouched.
+pub(crate) fn decode(method: Method, mut res: Response) -> Response {
+    if method == Method::Head
+        || matches!(
+            res.status(),
+            StatusCode::NoContent | StatusCode::NotModified
+        )
+    {
+        return res;
+    }
+
+    let encodings: Vec<String> = match res.header(CONTENT_ENCODING) {
+        Some(values) => values
+            .iter()
+            .flat_map(|value| value.as_str().split(','))
+            .map(|encoding| encoding.trim().to_ascii_lowercase())
+            .filter(|encoding| !encoding.is_empty() && encoding != "identity")
+            .collect(),
+        None => return res,
+    };
+    if encodings.is_empty() || !encodings.iter().all(|e| is_supported(e)) {
+        return res;
+    }
+
+    let body = res.take_body();
+    let mime = body.mime().clone();
+
+    // Encodings are listed in the order they were applied, so they are undone in reverse.
+    let mut reader: Box<dyn AsyncBufRead + Unpin + Send + Sync> = Box::new(body);
+    for encoding in encodings.iter().rev() {
+        reader = match encoding.as_str() {
+            "gzip" | "x-gzip" => Box::new(BufReader::new(GzipDecoder::new(reader))),
+            "deflate" => Box::new(BufReader::new(ZlibDecoder::new(reader))),
+            "br" => Box::new(BufReader::new(BrotliDecoder::new(reader))),
+            "zstd" => Box::new(BufReader::new(ZstdDecoder::new(reader))),
+            _ => unreachable!(),
+        };
+    }
+
+    let mut body = Body::from_reader(reader, None);
+    body.set_mime(mime);
+    res.set_body(body);
+    res.remove_header(CONTENT_ENCODING);
+    res.remove_header(CONTENT_LENGTH);
+    res
+}
+
+fn is_supported(encoding: &str) -> bool {
+    matches!(encoding, "gzip" | "x-gzip" | "deflate" | "br" | "zstd")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_compression::futures::bufread::{BrotliEncoder, GzipEncoder};
+    use futures_util::io::AsyncReadExt;
+
+    async fn encode(encoding: &str, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        match encoding {
+            "gzip" => GzipEncoder::new(data).read_to_end(&mut out).await.unwrap(),
+            "br" => BrotliEncoder::new(data)
+                .read_to_end(&mut out)
+                .await
+                .unwrap(),
+            _ => unreachable!(),
+        };
+        out
+    }
+
+    #[async_std::test]
+    async fn decodes_stacked_encodings() -> http_types::Result<()> {
+        let gzipped = encode("gzip", b"hello world").await;
+        let encoded = encode("br", &gzipped).await;
+
+        let mut res = Response::new(StatusCode::Ok);
+        res.insert_header(CONTENT_ENCODING, "gzip, br");
+        res.insert_header(CONTENT_LENGTH, encoded.len().to_string());
+        res.set_body(encoded);
+
+        let mut res = decode(Method::Get, res);
+        assert!(res.header(CONTENT_ENCODING).is_none());
+        assert!(res.header(CONTENT_LENGTH).is_none());
+        assert_eq!(res.body_string().await?, "hello world");
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn leaves_unknown_encodings_untouched() -> http_types::Result<()> {
+        let mut res = Response::new(StatusCode::Ok);
+        res.insert_header(CONTENT_ENCODING, "gzip, compress");
+        res.set_body("not really compressed");
+
+        let mut res = decode(Method::Get, res);
+        assert_eq!(res[CONTENT_ENCODING], "gzip, compress");
+        assert_eq!(res.body_string().await?, "not really compressed");
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_explicit_accept_encoding() {
+        let mut req = Request::new(Method::Get, "http://example.com");
+        prepare(&mut req);
+        assert_eq!(req[ACCEPT_ENCODING], ACCEPT_ENCODING_VALUE);
+
+        let mut req = Request::new(Method::Get, "http://example.com");
+        req.insert_header(ACCEPT_ENCODING, "identity");
+        prepare(&mut req);
+        assert_eq!(req[ACCEPT_ENCODING], "identity");
+    }
+}
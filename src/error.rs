@@ -0,0 +1,382 @@
+//! Errors reported by the backends.
+
+use std::error::Error as StdError;
+use std::fmt::{self, Display};
+use std::io;
+use std::time::Duration;
+
+use http_types::StatusCode;
+
+use crate::Error;
+
+/// What went wrong with a request, the same way on every backend.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ClientErrorKind {
+    /// Resolving a hostname failed.
+    Dns,
+    /// Opening a connection failed.
+    Connect,
+    /// The proxy refused the request, or failed to open a tunnel for it.
+    Proxy,
+    /// The TLS handshake failed.
+    Tls,
+    /// The certificate of the server had none of the keys pinned for its host in
+    /// `Config::key_pins`.
+    PinnedKeyMismatch,
+    /// One of the timeouts in `Config` elapsed.
+    Timeout(TimeoutKind),
+    /// The request was aborted before it completed, by something other than a timeout, such as
+    /// the browser stopping the fetch of `wasm_client`.
+    Cancelled,
+    /// The connection failed while sending the request or receiving the response head.
+    Io,
+    /// The response was not valid HTTP.
+    Protocol,
+    /// Reading the response body failed.
+    BodyRead,
+    /// The request URL cannot be sent, such as one without a host or with an unsupported scheme.
+    InvalidUrl,
+    /// A redirect policy gave up following redirects.
+    TooManyRedirects,
+    /// Anything else.
+    Other,
+}
+
+impl Display for ClientErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientErrorKind::Dns => f.write_str("DNS error"),
+            ClientErrorKind::Connect => f.write_str("connect error"),
+            ClientErrorKind::Proxy => f.write_str("proxy error"),
+            ClientErrorKind::Tls => f.write_str("TLS error"),
+            ClientErrorKind::PinnedKeyMismatch => f.write_str("pinned key mismatch"),
+            ClientErrorKind::Timeout(kind) => write!(f, "{} timeout", kind),
+            ClientErrorKind::Cancelled => f.write_str("request cancelled"),
+            ClientErrorKind::Io => f.write_str("I/O error"),
+            ClientErrorKind::Protocol => f.write_str("protocol error"),
+            ClientErrorKind::BodyRead => f.write_str("body read error"),
+            ClientErrorKind::InvalidUrl => f.write_str("invalid URL"),
+            ClientErrorKind::TooManyRedirects => f.write_str("too many redirects"),
+            ClientErrorKind::Other => f.write_str("other error"),
+        }
+    }
+}
+
+/// A failure to send a request or to read its response, and what kind of failure it was.
+///
+/// Errors returned by `HttpClient::send` hold a `ClientError`, as do the `io::Error`s returned
+/// when reading a response body fails. Use `ClientError::of` to find it in either.
+///
+/// # Examples
+///
+/// ```no_run
+/// use http_client::{ClientError, ClientErrorKind, HttpClient, Request};
+/// use http_types::Method;
+///
+/// # async fn example(client: impl HttpClient) {
+/// let req = Request::new(Method::Get, "http://example.com");
+/// if let Err(err) = client.send(req).await {
+///     match ClientError::of(&err).map(ClientError::kind) {
+///         Some(ClientErrorKind::Dns) => println!("no such host"),
+///         Some(ClientErrorKind::Timeout(kind)) => println!("{} timeout", kind),
+///         _ => println!("request failed: {}", err),
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ClientError {
+    kind: ClientErrorKind,
+    error: Box<dyn StdError + Send + Sync>,
+}
+
+impl ClientError {
+    /// Create a new client error of `kind`, caused by `error`.
+    pub fn new<E>(kind: ClientErrorKind, error: E) -> Self
+    where
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        Self {
+            kind,
+            error: error.into(),
+        }
+    }
+
+    /// What kind of failure this was.
+    pub fn kind(&self) -> ClientErrorKind {
+        self.kind
+    }
+
+    /// The underlying error.
+    pub fn get_ref(&self) -> &(dyn StdError + Send + Sync + 'static) {
+        &*self.error
+    }
+
+    /// Consume the client error, returning the underlying error.
+    pub fn into_inner(self) -> Box<dyn StdError + Send + Sync> {
+        self.error
+    }
+
+    /// The client error behind `err`, returned either by `HttpClient::send` or by reading a
+    /// response body.
+    pub fn of(err: &Error) -> Option<&ClientError> {
+        err.downcast_ref::<ClientError>().or_else(|| {
+            err.downcast_ref::<io::Error>()?
+                .get_ref()?
+                .downcast_ref::<ClientError>()
+        })
+    }
+
+    /// Convert into an `Error`, with the status code for its kind: `408 Request Timeout` where a
+    /// timeout elapsed, `400 Bad Request` where the request otherwise never got a response from
+    /// the server, as the backends have always reported such failures, and
+    /// `500 Internal Server Error` otherwise.
+    pub(crate) fn into_error(self) -> Error {
+        let status = match self.kind {
+            ClientErrorKind::Timeout(_) => StatusCode::RequestTimeout,
+            ClientErrorKind::Dns
+            | ClientErrorKind::Connect
+            | ClientErrorKind::Proxy
+            | ClientErrorKind::Tls
+            | ClientErrorKind::PinnedKeyMismatch
+            | ClientErrorKind::Cancelled
+            | ClientErrorKind::InvalidUrl
+            | ClientErrorKind::TooManyRedirects => StatusCode::BadRequest,
+            ClientErrorKind::Io
+            | ClientErrorKind::Protocol
+            | ClientErrorKind::BodyRead
+            | ClientErrorKind::Other => StatusCode::InternalServerError,
+        };
+        Error::new(status, self)
+    }
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.error, f)
+    }
+}
+
+impl StdError for ClientError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.error.source()
+    }
+}
+
+impl From<ClientError> for io::Error {
+    fn from(err: ClientError) -> Self {
+        let kind = match err.kind {
+            ClientErrorKind::Timeout(_) => io::ErrorKind::TimedOut,
+            _ => match err.error.downcast_ref::<io::Error>() {
+                Some(inner) => inner.kind(),
+                None => io::ErrorKind::Other,
+            },
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// Which of the timeouts in `Config` elapsed.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TimeoutKind {
+    /// `Config::connect_timeout`.
+    Connect,
+    /// `Config::tls_handshake_timeout`.
+    TlsHandshake,
+    /// `Config::response_header_timeout`, or `Config::timeout`.
+    ResponseHeader,
+    /// `Config::read_idle_timeout`.
+    ReadIdle,
+    /// `Config::total_timeout`.
+    Total,
+}
+
+impl Display for TimeoutKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TimeoutKind::Connect => "connect",
+            TimeoutKind::TlsHandshake => "TLS handshake",
+            TimeoutKind::ResponseHeader => "response header",
+            TimeoutKind::ReadIdle => "read idle",
+            TimeoutKind::Total => "total",
+        })
+    }
+}
+
+/// A request which ran out of time.
+///
+/// Timeouts are reported as a `ClientError` of kind `ClientErrorKind::Timeout` holding a
+/// `TimeoutError`. Use `TimeoutError::of` to find out which timeout elapsed, and after how long.
+///
+/// # Examples
+///
+/// ```no_run
+/// use http_client::{HttpClient, Request, TimeoutError, TimeoutKind};
+/// use http_types::Method;
+///
+/// # async fn example(client: impl HttpClient) {
+/// let req = Request::new(Method::Get, "http://example.com");
+/// if let Err(err) = client.send(req).await {
+///     if let Some(timeout) = TimeoutError::of(&err) {
+///         assert_eq!(timeout.kind(), TimeoutKind::Connect);
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TimeoutError {
+    kind: TimeoutKind,
+    duration: Duration,
+}
+
+impl TimeoutError {
+    /// Which timeout elapsed.
+    pub fn kind(&self) -> TimeoutKind {
+        self.kind
+    }
+
+    /// The duration of the timeout which elapsed.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// The timeout behind `err`, if it is one.
+    pub fn of(err: &Error) -> Option<&TimeoutError> {
+        ClientError::of(err)?.get_ref().downcast_ref()
+    }
+}
+
+impl Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} timeout of {:?} elapsed", self.kind, self.duration)
+    }
+}
+
+impl StdError for TimeoutError {}
+
+impl From<TimeoutError> for ClientError {
+    fn from(err: TimeoutError) -> Self {
+        ClientError::new(ClientErrorKind::Timeout(err.kind), err)
+    }
+}
+
+impl From<TimeoutError> for io::Error {
+    fn from(err: TimeoutError) -> Self {
+        ClientError::from(err).into()
+    }
+}
+
+// Only the backends report errors.
+cfg_if::cfg_if! {
+    if #[cfg(any(
+        feature = "h1_client",
+        feature = "hyper_client",
+        all(feature = "curl_client", not(target_arch = "wasm32")),
+        all(feature = "wasm_client", target_arch = "wasm32")
+    ))] {
+        use std::pin::Pin;
+        use std::task::{Context, Poll};
+
+        use futures_util::io::{AsyncBufRead, AsyncRead};
+
+        use crate::{Body, Response};
+
+        impl ClientError {
+            /// The client error held by `err`, or a new one of `kind` wrapping it.
+            pub(crate) fn from_io(err: io::Error, kind: ClientErrorKind) -> Self {
+                if matches!(err.get_ref(), Some(inner) if inner.is::<ClientError>()) {
+                    // Just checked, so neither can fail.
+                    let inner = err.into_inner().unwrap();
+                    return *inner.downcast::<ClientError>().unwrap();
+                }
+                Self::new(kind, err)
+            }
+        }
+
+        impl TimeoutError {
+            pub(crate) fn new(kind: TimeoutKind, duration: Duration) -> Self {
+                Self { kind, duration }
+            }
+        }
+
+        /// Attach `kind` to `err`, unless it already holds a `ClientError`. Errors other than
+        /// `io::Error`s keep their status.
+        pub(crate) fn classify(err: Error, kind: ClientErrorKind) -> Error {
+            if err.downcast_ref::<ClientError>().is_some() {
+                return err;
+            }
+            match err.downcast::<io::Error>() {
+                Ok(err) => ClientError::from_io(err, kind).into_error(),
+                Err(err) => {
+                    let status = err.status();
+                    let mut err = ClientError::new(kind, err.into_inner()).into_error();
+                    err.set_status(status);
+                    err
+                }
+            }
+        }
+
+        /// Mark failures reading the body of `res` as `ClientErrorKind::BodyRead`.
+        pub(crate) fn body_read_errors(mut res: Response) -> Response {
+            let body = res.take_body();
+            let len = body.len();
+            let mime = body.mime().clone();
+
+            let mut body = Body::from_reader(BodyReadErrors(body), len);
+            body.set_mime(mime);
+            res.set_body(body);
+            res
+        }
+
+        struct BodyReadErrors(Body);
+
+        fn body_read_error(err: io::Error) -> io::Error {
+            ClientError::from_io(err, ClientErrorKind::BodyRead).into()
+        }
+
+        impl AsyncRead for BodyReadErrors {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut [u8],
+            ) -> Poll<io::Result<usize>> {
+                Pin::new(&mut self.0)
+                    .poll_read(cx, buf)
+                    .map_err(body_read_error)
+            }
+        }
+
+        impl AsyncBufRead for BodyReadErrors {
+            fn poll_fill_buf(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+            ) -> Poll<io::Result<&[u8]>> {
+                Pin::new(&mut self.get_mut().0)
+                    .poll_fill_buf(cx)
+                    .map_err(body_read_error)
+            }
+
+            fn consume(mut self: Pin<&mut Self>, amt: usize) {
+                Pin::new(&mut self.0).consume(amt)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses_by_kind() {
+        let status = |kind| ClientError::new(kind, "failed").into_error().status();
+        assert_eq!(status(ClientErrorKind::Connect), StatusCode::BadRequest);
+        assert_eq!(
+            status(ClientErrorKind::Timeout(TimeoutKind::Total)),
+            StatusCode::RequestTimeout
+        );
+        assert_eq!(status(ClientErrorKind::Io), StatusCode::InternalServerError);
+    }
+}
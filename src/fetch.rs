@@ -0,0 +1,174 @@
+//! Options of the fetch API, which `wasm_client` sends requests with.
+
+/// The options `wasm_client` gives the browser for each fetch, beyond the method, headers and
+/// body of the request.
+///
+/// Set them for every request with `Config::set_fetch`, or for one request by inserting them
+/// into its extensions, where they replace those in `Config`. Options left unset are up to the
+/// browser.
+///
+/// Note: Does nothing on other backends.
+///
+/// # Examples
+///
+/// ```
+/// use http_client::{FetchCredentials, FetchMode, FetchOptions, Request};
+/// use http_types::Method;
+///
+/// let options = FetchOptions::new()
+///     .set_mode(Some(FetchMode::Cors))
+///     .set_credentials(Some(FetchCredentials::Include));
+/// let mut req = Request::new(Method::Get, "https://api.example.com/me");
+/// req.ext_mut().insert(options);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FetchOptions {
+    pub(crate) mode: Option<FetchMode>,
+    pub(crate) credentials: Option<FetchCredentials>,
+    pub(crate) cache: Option<FetchCache>,
+    pub(crate) redirect: Option<FetchRedirect>,
+    pub(crate) referrer: Option<String>,
+    pub(crate) referrer_policy: Option<ReferrerPolicy>,
+    pub(crate) integrity: Option<String>,
+    pub(crate) keepalive: bool,
+}
+
+impl FetchOptions {
+    /// Create new options, which leave everything up to the browser.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether cross-origin requests are made with CORS.
+    pub fn set_mode(mut self, mode: Option<FetchMode>) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Set whether cookies and HTTP authentication are sent, and received cookies stored.
+    pub fn set_credentials(mut self, credentials: Option<FetchCredentials>) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Set how the request uses the HTTP cache of the browser.
+    pub fn set_cache(mut self, cache: Option<FetchCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Set what the browser does with redirects.
+    ///
+    /// Note: `middleware::Redirect` cannot follow redirects in browsers, which either follow them
+    /// before it sees the response or, with `FetchRedirect::Manual`, return an opaque response
+    /// in their place.
+    pub fn set_redirect(mut self, redirect: Option<FetchRedirect>) -> Self {
+        self.redirect = redirect;
+        self
+    }
+
+    /// Set the referrer, a same-origin URL, or an empty string to send none.
+    pub fn set_referrer(mut self, referrer: Option<String>) -> Self {
+        self.referrer = referrer;
+        self
+    }
+
+    /// Set how much of the referrer is sent.
+    pub fn set_referrer_policy(mut self, referrer_policy: Option<ReferrerPolicy>) -> Self {
+        self.referrer_policy = referrer_policy;
+        self
+    }
+
+    /// Set the subresource integrity metadata the response body must match, such as
+    /// `sha256-...`.
+    pub fn set_integrity(mut self, integrity: Option<String>) -> Self {
+        self.integrity = integrity;
+        self
+    }
+
+    /// Set whether the request may outlive the page which made it.
+    ///
+    /// Default: `false`.
+    pub fn set_keepalive(mut self, keepalive: bool) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+}
+
+/// Whether cross-origin requests are made with CORS, as in the `mode` of a fetch.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FetchMode {
+    /// Make cross-origin requests with CORS.
+    Cors,
+    /// Make cross-origin requests without CORS, receiving opaque responses.
+    NoCors,
+    /// Refuse cross-origin requests.
+    SameOrigin,
+    /// Navigate between documents.
+    Navigate,
+}
+
+/// Whether cookies and HTTP authentication are used, as in the `credentials` of a fetch.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FetchCredentials {
+    /// Never.
+    Omit,
+    /// Only for same-origin requests.
+    SameOrigin,
+    /// Always, including for cross-origin requests.
+    Include,
+}
+
+/// How a request uses the HTTP cache, as in the `cache` of a fetch.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FetchCache {
+    /// Use fresh responses from the cache, and revalidate stale ones.
+    Default,
+    /// Neither read nor write the cache.
+    NoStore,
+    /// Skip the cache, and store the response in it.
+    Reload,
+    /// Revalidate any response in the cache.
+    NoCache,
+    /// Use any response in the cache, however stale.
+    ForceCache,
+    /// Use any response in the cache, and fail without one. Only for same-origin requests.
+    OnlyIfCached,
+}
+
+/// What the browser does with redirects, as in the `redirect` of a fetch.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FetchRedirect {
+    /// Follow them.
+    Follow,
+    /// Fail the request.
+    Error,
+    /// Return an opaque response for them.
+    Manual,
+}
+
+/// How much of the referrer is sent, as in the `Referrer-Policy` header.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ReferrerPolicy {
+    /// `no-referrer`.
+    NoReferrer,
+    /// `no-referrer-when-downgrade`.
+    NoReferrerWhenDowngrade,
+    /// `origin`.
+    Origin,
+    /// `origin-when-cross-origin`.
+    OriginWhenCrossOrigin,
+    /// `same-origin`.
+    SameOrigin,
+    /// `strict-origin`.
+    StrictOrigin,
+    /// `strict-origin-when-cross-origin`.
+    StrictOriginWhenCrossOrigin,
+    /// `unsafe-url`.
+    UnsafeUrl,
+}
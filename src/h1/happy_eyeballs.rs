@@ -0,0 +1,141 @@
+//! Racing connections to the addresses of a host, as in RFC 8305 ("Happy Eyeballs").
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures_timer::Delay;
+use futures_util::future::{self, Either};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+
+/// Connect to one of `addrs`, which must not be empty, with `connect`.
+///
+/// Attempts are started on IPv6 and IPv4 addresses in turn, each one `delay` after the last, or
+/// as soon as an attempt fails. The first to succeed wins, and the rest are dropped. Without a
+/// `delay`, each attempt waits for the last one to fail. When every attempt fails, the error of
+/// the last one is returned.
+pub(crate) async fn race<T, E, F, Fut>(
+    addrs: Vec<SocketAddr>,
+    delay: Option<Duration>,
+    mut connect: F,
+) -> Result<T, E>
+where
+    F: FnMut(SocketAddr) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut addrs = interleave(addrs).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_err = None;
+
+    loop {
+        if attempts.is_empty() {
+            match addrs.next() {
+                Some(addr) => attempts.push(connect(addr)),
+                None => return Err(last_err.expect("no addresses to connect to")),
+            }
+        }
+
+        let next_attempt = match delay {
+            Some(delay) if !addrs.as_slice().is_empty() => Either::Left(Delay::new(delay)),
+            _ => Either::Right(future::pending()),
+        };
+        match future::select(attempts.next(), next_attempt).await {
+            Either::Left((Some(Ok(conn)), _)) => return Ok(conn),
+            Either::Left((Some(Err(err)), _)) => {
+                last_err = Some(err);
+                if let Some(addr) = addrs.next() {
+                    attempts.push(connect(addr));
+                }
+            }
+            Either::Left((None, _)) => unreachable!("an attempt is always in progress"),
+            Either::Right(_) => {
+                if let Some(addr) = addrs.next() {
+                    attempts.push(connect(addr));
+                }
+            }
+        }
+    }
+}
+
+/// Alternate between IPv6 and IPv4 addresses, starting with the family of the first address.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_ipv6 = matches!(addrs.first(), Some(addr) if addr.is_ipv6());
+    let (first, second): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_is_ipv6);
+
+    let mut interleaved = Vec::with_capacity(first.len() + second.len());
+    let (mut first, mut second) = (first.into_iter(), second.into_iter());
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => return interleaved,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn addrs(addrs: &[&str]) -> Vec<SocketAddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn interleaves_families() {
+        let interleaved = interleave(addrs(&[
+            "[::1]:80",
+            "[::2]:80",
+            "[::3]:80",
+            "127.0.0.1:80",
+            "127.0.0.2:80",
+        ]));
+        let expected = addrs(&[
+            "[::1]:80",
+            "127.0.0.1:80",
+            "[::2]:80",
+            "127.0.0.2:80",
+            "[::3]:80",
+        ]);
+        assert_eq!(interleaved, expected);
+    }
+
+    /// Connect to `addrs`, where `[::1]` never answers and `[::2]` fails straight away.
+    async fn connect(
+        addrs: &[&str],
+        delay: Option<Duration>,
+    ) -> (Result<SocketAddr, String>, Duration) {
+        let start = Instant::now();
+        let res = race(self::addrs(addrs), delay, |addr| async move {
+            match addr.ip().to_string().as_str() {
+                "::1" => future::pending().await,
+                "::2" => Err(format!("{} refused", addr)),
+                _ => Ok(addr),
+            }
+        })
+        .await;
+        (res, start.elapsed())
+    }
+
+    #[async_std::test]
+    async fn races_after_delay() {
+        let delay = Some(Duration::from_millis(50));
+        let (res, elapsed) = connect(&["[::1]:80", "127.0.0.1:80"], delay).await;
+        assert_eq!(res.unwrap(), "127.0.0.1:80".parse().unwrap());
+        assert!(elapsed >= Duration::from_millis(50));
+        assert!(elapsed < Duration::from_secs(1));
+    }
+
+    #[async_std::test]
+    async fn moves_on_when_attempts_fail() {
+        let delay = Some(Duration::from_secs(10));
+        let (res, elapsed) = connect(&["[::2]:80", "127.0.0.1:80"], delay).await;
+        assert_eq!(res.unwrap(), "127.0.0.1:80".parse().unwrap());
+        assert!(elapsed < Duration::from_secs(1));
+
+        let (res, _) = connect(&["[::2]:80", "[::2]:81"], None).await;
+        assert_eq!(res.unwrap_err(), "[::2]:81 refused");
+    }
+}
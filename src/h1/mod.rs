@@ -3,81 +3,140 @@
 use std::convert::{Infallible, TryFrom};
 use std::fmt::Debug;
 use std::net::SocketAddr;
-use std::sync::Arc;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Once};
+use std::time::Instant;
 
 use async_h1::client;
 use async_std::net::TcpStream;
+#[cfg(unix)]
+use async_std::os::unix::net::UnixStream;
 use dashmap::DashMap;
-use deadpool::managed::Pool;
-use http_types::StatusCode;
+use deadpool::managed::{Object, PoolError};
+use event_listener::Event;
+use futures::io::{AsyncRead, AsyncWrite};
+use http_types::headers::{HeaderValue, PROXY_AUTHORIZATION};
+use http_types::url::Url;
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "rustls")] {
-        use async_tls::client::TlsStream;
+        use futures_rustls::client::TlsStream;
     } else if #[cfg(feature = "native-tls")] {
         use async_native_tls::TlsStream;
     }
 }
 
-use crate::Config;
+use crate::error::{body_read_errors, classify};
+use crate::{resolve, timeout};
+use crate::{ClientError, ClientErrorKind, Config, Proxy, TimeoutKind};
 
 use super::{async_trait, Error, HttpClient, Request, Response};
 
+mod happy_eyeballs;
+mod pool;
+mod proxy;
 mod tcp;
 #[cfg(any(feature = "native-tls", feature = "rustls"))]
 mod tls;
+#[cfg(unix)]
+mod unix;
 
+use crate::proxy::tunnel::Tunnel;
+pub use pool::PoolStats;
+use pool::{ConnPool, Conns, PoolMap, Pooled};
+use proxy::AbsoluteForm;
 use tcp::{TcpConnWrapper, TcpConnection};
 #[cfg(any(feature = "native-tls", feature = "rustls"))]
-use tls::{TlsConnWrapper, TlsConnection};
+use tls::{Connector, TlsConnWrapper, TlsConnection};
+#[cfg(unix)]
+pub use unix::UnixSocket;
+#[cfg(unix)]
+use unix::{UnixConnWrapper, UnixConnection};
 
-type HttpPool = DashMap<SocketAddr, Pool<TcpStream, std::io::Error>>;
+/// The connections a pool holds: for requests to the origin at `scheme://host:port`, through
+/// `proxy` if any, to `addr`, which is that of the proxy when there is one.
+///
+/// Pooling connections per origin, rather than per address, keeps hosts which share an address
+/// from being sent requests over a TLS connection negotiated for another. Pooling them per proxy
+/// keeps connections through one proxy from being used once the config names another, or none.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct PoolKey {
+    scheme: String,
+    host: String,
+    port: u16,
+    proxy: Option<Url>,
+    addr: SocketAddr,
+}
+
+impl PoolKey {
+    fn new(url: &Url, proxy: Option<&Url>, addr: SocketAddr) -> Self {
+        Self {
+            scheme: url.scheme().to_string(),
+            host: url.host_str().unwrap_or_default().to_string(),
+            port: url.port_or_known_default().unwrap_or_default(),
+            proxy: proxy.cloned(),
+            addr,
+        }
+    }
+
+    fn origin(&self) -> String {
+        format!("{}://{}:{}", self.scheme, self.host, self.port)
+    }
+}
+
+type HttpPool = DashMap<PoolKey, ConnPool<TcpStream, std::io::Error>>;
 #[cfg(any(feature = "native-tls", feature = "rustls"))]
-type HttpsPool = DashMap<SocketAddr, Pool<TlsStream<TcpStream>, Error>>;
+type HttpsPool = DashMap<PoolKey, ConnPool<TlsStream<TcpStream>, Error>>;
+#[cfg(unix)]
+type UnixPool = DashMap<PathBuf, ConnPool<UnixStream, std::io::Error>>;
+
+/// Where the connections for requests to a URL go.
+struct Route {
+    /// The host of the URL.
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    host: String,
+    /// The addresses of the host, or of the proxy in front of it.
+    addrs: Vec<SocketAddr>,
+    /// The URL of the proxy in front of the host, if any.
+    proxy: Option<Url>,
+    /// The tunnel through the proxy to the host.
+    tunnel: Option<Tunnel>,
+    /// Whether requests are sent to an HTTP proxy in absolute-form.
+    absolute_form: bool,
+    /// The `Proxy-Authorization` header for requests in absolute-form, if any.
+    proxy_authorization: Option<HeaderValue>,
+}
 
 /// async-h1 based HTTP Client, with connection pooling ("Keep-Alive").
 pub struct H1Client {
-    http_pools: HttpPool,
+    http_pools: Arc<HttpPool>,
     #[cfg(any(feature = "native-tls", feature = "rustls"))]
-    https_pools: HttpsPool,
+    https_pools: Arc<HttpsPool>,
+    #[cfg(unix)]
+    unix_pools: Arc<UnixPool>,
+    reaper: Once,
+    shut_down: AtomicBool,
+    /// Notified when a connection is released by a request, or closed.
+    released: Arc<Event>,
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    connector: tls::Connector,
     config: Arc<Config>,
 }
 
 impl Debug for H1Client {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let https_pools = if cfg!(any(feature = "native-tls", feature = "rustls")) {
-            self.http_pools
-                .iter()
-                .map(|pool| {
-                    let status = pool.status();
-                    format!(
-                        "Connections: {}, Available: {}, Max: {}",
-                        status.size, status.available, status.max_size
-                    )
-                })
-                .collect::<Vec<String>>()
-        } else {
-            vec![]
-        };
+        let mut dbg_struct = f.debug_struct("H1Client");
+        dbg_struct.field("http_pools", &self.http_pools);
 
-        f.debug_struct("H1Client")
-            .field(
-                "http_pools",
-                &self
-                    .http_pools
-                    .iter()
-                    .map(|pool| {
-                        let status = pool.status();
-                        format!(
-                            "Connections: {}, Available: {}, Max: {}",
-                            status.size, status.available, status.max_size
-                        )
-                    })
-                    .collect::<Vec<String>>(),
-            )
-            .field("https_pools", &https_pools)
-            .field("config", &self.config)
-            .finish()
+        #[cfg(any(feature = "native-tls", feature = "rustls"))]
+        dbg_struct.field("https_pools", &self.https_pools);
+
+        #[cfg(unix)]
+        dbg_struct.field("unix_pools", &self.unix_pools);
+
+        dbg_struct.field("config", &self.config).finish()
     }
 }
 
@@ -91,9 +150,16 @@ impl H1Client {
     /// Create a new instance.
     pub fn new() -> Self {
         Self {
-            http_pools: DashMap::new(),
+            http_pools: Arc::new(DashMap::new()),
             #[cfg(any(feature = "native-tls", feature = "rustls"))]
-            https_pools: DashMap::new(),
+            https_pools: Arc::new(DashMap::new()),
+            #[cfg(unix)]
+            unix_pools: Arc::new(DashMap::new()),
+            reaper: Once::new(),
+            shut_down: AtomicBool::new(false),
+            released: Arc::new(Event::new()),
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            connector: Connector::new(&Config::default()),
             config: Arc::new(Config::default()),
         }
     }
@@ -104,7 +170,7 @@ impl H1Client {
         note = "This function is misnamed. Prefer `Config::max_connections_per_host` instead."
     )]
     pub fn with_max_connections(max: usize) -> Self {
-        #[cfg(features = "h1_client")]
+        #[cfg(feature = "h1_client")]
         assert!(max > 0, "max_connections_per_host with h1_client must be greater than zero or it will deadlock!");
 
         let config = Config {
@@ -113,187 +179,529 @@ impl H1Client {
         };
 
         Self {
-            http_pools: DashMap::new(),
+            http_pools: Arc::new(DashMap::new()),
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            https_pools: Arc::new(DashMap::new()),
+            #[cfg(unix)]
+            unix_pools: Arc::new(DashMap::new()),
+            reaper: Once::new(),
+            shut_down: AtomicBool::new(false),
+            released: Arc::new(Event::new()),
             #[cfg(any(feature = "native-tls", feature = "rustls"))]
-            https_pools: DashMap::new(),
+            connector: Connector::new(&config),
             config: Arc::new(config),
         }
     }
+
+    /// The state of the pool of connections to each origin, for `http` and `https` requests.
+    ///
+    /// Connections to an origin are pooled per address, so there is an entry for each address an
+    /// origin was connected to. Entries are sorted by origin, then by address.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use http_client::h1::H1Client;
+    ///
+    /// let client = H1Client::new();
+    /// for stats in client.pool_stats() {
+    ///     println!("{} at {}: {} open", stats.origin, stats.addr, stats.size);
+    /// }
+    /// ```
+    pub fn pool_stats(&self) -> Vec<PoolStats> {
+        let mut stats: Vec<_> = self
+            .http_pools
+            .iter()
+            .map(|pool| pool.stats(pool.key().origin(), pool.key().addr))
+            .collect();
+        #[cfg(any(feature = "native-tls", feature = "rustls"))]
+        stats.extend(
+            self.https_pools
+                .iter()
+                .map(|pool| pool.stats(pool.key().origin(), pool.key().addr)),
+        );
+        stats.sort_by(|a, b| (&a.origin, a.addr).cmp(&(&b.origin, b.addr)));
+        stats
+    }
+
+    /// Open connections to the origin of `url` until its pool holds `n`, or
+    /// `Config::max_connections_per_host` if that is fewer, so that requests to it do not wait
+    /// to connect.
+    ///
+    /// Does nothing without `Config::http_keep_alive`. Fails as a request to `url` would when
+    /// connecting fails.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use http_client::h1::H1Client;
+    /// use http_types::url::Url;
+    ///
+    /// # #[async_std::main]
+    /// # async fn main() -> Result<(), http_types::Error> {
+    /// let client = H1Client::new();
+    /// client.warm_up(&Url::parse("https://example.com")?, 4).await?;
+    /// # Ok(()) }
+    /// ```
+    pub async fn warm_up(&self, url: &Url, n: usize) -> Result<(), Error> {
+        if self.shut_down.load(Ordering::Relaxed) {
+            return Err(shut_down());
+        }
+        if !self.config.http_keep_alive {
+            return Ok(());
+        }
+
+        let route = self.route(url).await?;
+        let n = n.min(self.config.max_connections_per_host);
+        // Connections are held until all are open, so that each is a different one, then parked
+        // with the idle connections of their pool.
+        match url.scheme() {
+            "http" => {
+                let conns = (0..n).map(|_| self.pooled_http(url, &route));
+                for mut conn in futures::future::try_join_all(conns).await? {
+                    conn.park();
+                }
+            }
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            "https" => {
+                let conns = (0..n).map(|_| self.pooled_https(url, &route));
+                for mut conn in futures::future::try_join_all(conns).await? {
+                    conn.park();
+                }
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Close every idle connection in the pools, leaving those in use open.
+    pub async fn close_idle(&self) {
+        for pools in self.pool_maps() {
+            pools.close_idle().await;
+        }
+    }
+
+    /// Stop sending requests, and close every connection once the requests in flight are done
+    /// with it, waiting until `deadline` at the latest.
+    ///
+    /// Returns whether every connection was closed. Requests sent afterwards fail.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::time::{Duration, Instant};
+    ///
+    /// use http_client::h1::H1Client;
+    ///
+    /// # #[async_std::main]
+    /// # async fn main() {
+    /// let client = H1Client::new();
+    /// // ...
+    /// if !client.shutdown(Instant::now() + Duration::from_secs(5)).await {
+    ///     println!("some requests were still in flight");
+    /// }
+    /// # }
+    /// ```
+    pub async fn shutdown(&self, deadline: Instant) -> bool {
+        self.shut_down.store(true, Ordering::Relaxed);
+        loop {
+            // Listening before closing means a connection released meanwhile is not missed.
+            let released = self.released.listen();
+            self.close_idle().await;
+            let open: usize = self.pool_maps().iter().map(|pools| pools.open()).sum();
+            let now = Instant::now();
+            if open == 0 || now >= deadline {
+                return open == 0;
+            }
+            // Connections go back into their pools as requests finish with them.
+            let _ = async_std::future::timeout(deadline - now, released).await;
+        }
+    }
 }
 
 #[async_trait]
 impl HttpClient for H1Client {
-    async fn send(&self, mut req: Request) -> Result<Response, Error> {
+    async fn send(&self, req: Request) -> Result<Response, Error> {
+        #[cfg(feature = "decompression")]
+        if self.config.decompress {
+            let mut req = req;
+            let method = req.method();
+            crate::decompress::prepare(&mut req);
+            let res = self.send_within_timeouts(req).await?;
+            return Ok(body_read_errors(crate::decompress::decode(method, res)));
+        }
+
+        let res = self.send_within_timeouts(req).await?;
+        Ok(body_read_errors(res))
+    }
+
+    /// Override the existing configuration with new configuration.
+    ///
+    /// Config options may not impact existing connections.
+    fn set_config(&mut self, config: Config) -> http_types::Result<()> {
+        #[cfg(feature = "h1_client")]
+        assert!(config.max_connections_per_host > 0, "max_connections_per_host with h1_client must be greater than zero or it will deadlock!");
+
+        #[cfg(any(feature = "native-tls", feature = "rustls"))]
+        {
+            self.connector = Connector::new(&config);
+        }
+        self.config = Arc::new(config);
+
+        Ok(())
+    }
+
+    /// Get the current configuration.
+    fn config(&self) -> &Config {
+        &self.config
+    }
+}
+
+impl H1Client {
+    async fn send_within_timeouts(&self, req: Request) -> Result<Response, Error> {
+        timeout::send(&self.config, self.dispatch(req))
+            .await
+            .map_err(|e| classify(e, ClientErrorKind::Other))
+    }
+
+    /// Send a request over a pooled (or, without keep-alive, a new) connection.
+    async fn dispatch(&self, mut req: Request) -> Result<Response, Error> {
+        if self.shut_down.load(Ordering::Relaxed) {
+            return Err(shut_down());
+        }
         req.insert_header("Connection", "keep-alive");
 
-        // Insert host
+        #[cfg(unix)]
+        if let Some(path) = unix::socket_path(&mut req)? {
+            return self.dispatch_unix(path, req).await;
+        }
+
+        let route = self.route(req.url()).await?;
+        if let Some(authorization) = route.proxy_authorization.clone() {
+            req.insert_header(PROXY_AUTHORIZATION, authorization);
+        }
+
+        log::trace!("> Scheme: {}", req.url().scheme());
+
+        let delay = self.config.happy_eyeballs_delay;
+        let tunnel = route.tunnel.as_ref();
+
+        if !self.config.http_keep_alive {
+            match req.url().scheme() {
+                "http" => {
+                    let stream = happy_eyeballs::race(route.addrs, delay, |addr| {
+                        tcp::connect(addr, tunnel, &self.config)
+                    })
+                    .await?;
+                    req.set_peer_addr(stream.peer_addr().ok());
+                    req.set_local_addr(stream.local_addr().ok());
+                    return self.send_over(stream, req, route.absolute_form).await;
+                }
+                #[cfg(any(feature = "native-tls", feature = "rustls"))]
+                "https" => {
+                    let host = route.host.as_str();
+                    let tls_stream = happy_eyeballs::race(route.addrs, delay, |addr| {
+                        tls::connect(host, addr, tunnel, &self.config, &self.connector)
+                    })
+                    .await?;
+                    req.set_peer_addr(tls::tcp_stream(&tls_stream).peer_addr().ok());
+                    req.set_local_addr(tls::tcp_stream(&tls_stream).local_addr().ok());
+                    let tls_info = tls::tls_info(&tls_stream);
+                    let mut res = self.send_over(tls_stream, req, false).await?;
+                    res.ext_mut().insert(tls_info);
+                    return Ok(res);
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        match req.url().scheme() {
+            "http" => {
+                let stream = self.pooled_http(req.url(), &route).await?;
+                req.set_peer_addr(stream.peer_addr().ok());
+                req.set_local_addr(stream.local_addr().ok());
+
+                self.send_over(TcpConnWrapper::new(stream), req, route.absolute_form)
+                    .await
+            }
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            "https" => {
+                let stream = self.pooled_https(req.url(), &route).await?;
+                req.set_peer_addr(tls::tcp_stream(&stream).peer_addr().ok());
+                req.set_local_addr(tls::tcp_stream(&stream).local_addr().ok());
+                let tls_info = tls::tls_info(&stream);
+
+                let mut res = self
+                    .send_over(TlsConnWrapper::new(stream), req, false)
+                    .await?;
+                res.ext_mut().insert(tls_info);
+                Ok(res)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Where the connections for requests to `url` go, failing unless its scheme is `http`, or
+    /// `https` with TLS enabled.
+    async fn route(&self, url: &Url) -> Result<Route, Error> {
         #[cfg(any(feature = "native-tls", feature = "rustls"))]
-        let host = req
-            .url()
+        let host = url
             .host_str()
-            .ok_or_else(|| Error::from_str(StatusCode::BadRequest, "missing hostname"))?
+            .ok_or_else(|| invalid_url("missing hostname"))?
             .to_string();
 
-        let scheme = req.url().scheme();
+        // An HTTP proxy is sent plain `http` requests in absolute-form. Anything else going
+        // through a proxy is tunnelled to its target.
+        let proxy = self.config.proxy_for(url);
+        let absolute_form = url.scheme() == "http"
+            && matches!(proxy, Some(proxy) if proxy.url().scheme() == "http");
+        let resolver = resolve::resolver(&self.config);
+        let tunnel = match proxy {
+            Some(proxy) if !absolute_form => Some(
+                Tunnel::new(proxy.clone(), url, resolver.clone())
+                    .ok_or_else(|| invalid_url("missing hostname"))?,
+            ),
+            _ => None,
+        };
+
+        let scheme = url.scheme();
         if scheme != "http"
             && (scheme != "https" || cfg!(not(any(feature = "native-tls", feature = "rustls"))))
         {
-            return Err(Error::from_str(
-                StatusCode::BadRequest,
-                format!("invalid url scheme '{}'", scheme),
-            ));
+            return Err(invalid_url(format!("invalid url scheme '{}'", scheme)));
         }
 
-        let addrs = req.url().socket_addrs(|| match req.url().scheme() {
-            "http" => Some(80),
+        // Through a proxy, connections are made to the proxy.
+        let addr_url = proxy.map_or(url, Proxy::url);
+        let addr_host = addr_url
+            .host_str()
+            .ok_or_else(|| invalid_url("missing hostname"))?;
+        // SOCKS proxies have no default port in URLs, but conventionally listen on 1080.
+        let port = addr_url.port_or_known_default().unwrap_or(1080);
+        let addrs = resolve::lookup(&*resolver, addr_host, port)
+            .await
+            .map_err(|e| ClientError::from_io(e, ClientErrorKind::Dns).into_error())?;
+
+        Ok(Route {
             #[cfg(any(feature = "native-tls", feature = "rustls"))]
-            "https" => Some(443),
-            _ => None,
-        })?;
+            host,
+            addrs,
+            proxy: proxy.map(|proxy| proxy.url().clone()),
+            tunnel,
+            absolute_form,
+            proxy_authorization: proxy
+                .filter(|_| absolute_form)
+                .and_then(Proxy::authorization),
+        })
+    }
 
-        log::trace!("> Scheme: {}", scheme);
-
-        let max_addrs_idx = addrs.len() - 1;
-        for (idx, addr) in addrs.into_iter().enumerate() {
-            let has_another_addr = idx != max_addrs_idx;
-
-            if !self.config.http_keep_alive {
-                match scheme {
-                    "http" => {
-                        let stream = async_std::net::TcpStream::connect(addr).await?;
-                        req.set_peer_addr(stream.peer_addr().ok());
-                        req.set_local_addr(stream.local_addr().ok());
-                        let tcp_conn = client::connect(stream, req);
-                        return if let Some(timeout) = self.config.timeout {
-                            async_std::future::timeout(timeout, tcp_conn).await?
-                        } else {
-                            tcp_conn.await
-                        };
-                    }
-                    #[cfg(any(feature = "native-tls", feature = "rustls"))]
-                    "https" => {
-                        let raw_stream = async_std::net::TcpStream::connect(addr).await?;
-                        req.set_peer_addr(raw_stream.peer_addr().ok());
-                        req.set_local_addr(raw_stream.local_addr().ok());
-                        let tls_stream = tls::add_tls(&host, raw_stream, &self.config).await?;
-                        let tsl_conn = client::connect(tls_stream, req);
-                        return if let Some(timeout) = self.config.timeout {
-                            async_std::future::timeout(timeout, tsl_conn).await?
-                        } else {
-                            tsl_conn.await
-                        };
+    /// Get a connection for a request to the `http` URL `url` from its pool, racing the
+    /// addresses of `route`.
+    async fn pooled_http(
+        &self,
+        url: &Url,
+        route: &Route,
+    ) -> Result<Object<Pooled<TcpStream>, std::io::Error>, Error> {
+        let tunnel = route.tunnel.as_ref();
+        let delay = self.config.happy_eyeballs_delay;
+        happy_eyeballs::race(route.addrs.clone(), delay, |addr| {
+            let key = PoolKey::new(url, route.proxy.as_ref(), addr);
+            // The pool is cloned out of its entry, which would lock its part of the map while
+            // awaited, and which the reaper may remove once released.
+            let pool = self
+                .http_pools
+                .entry(key)
+                .or_insert_with(|| {
+                    let conns = Arc::new(Conns::new(self.released.clone()));
+                    let manager = TcpConnection::new(
+                        addr,
+                        tunnel.cloned(),
+                        self.config.clone(),
+                        conns.clone(),
+                    );
+                    ConnPool::new(manager, conns, self.config.clone())
+                })
+                .clone();
+            self.start_reaper();
+
+            async move {
+                pool.get().await.map_err(|e| match e {
+                    PoolError::Backend(e) => {
+                        ClientError::from_io(e, ClientErrorKind::Connect).into_error()
                     }
-                    _ => unreachable!(),
-                }
+                    e => pool_error(e),
+                })
             }
+        })
+        .await
+    }
 
-            match scheme {
-                "http" => {
-                    let pool_ref = if let Some(pool_ref) = self.http_pools.get(&addr) {
-                        pool_ref
-                    } else {
-                        let manager = TcpConnection::new(addr, self.config.clone());
-                        let pool = Pool::<TcpStream, std::io::Error>::new(
-                            manager,
-                            self.config.max_connections_per_host,
-                        );
-                        self.http_pools.insert(addr, pool);
-                        self.http_pools.get(&addr).unwrap()
-                    };
-
-                    // Deadlocks are prevented by cloning an inner pool Arc and dropping the original locking reference before we await.
-                    let pool = pool_ref.clone();
-                    std::mem::drop(pool_ref);
-
-                    let stream = match pool.get().await {
-                        Ok(s) => s,
-                        Err(_) if has_another_addr => continue,
-                        Err(e) => return Err(Error::from_str(400, e.to_string())),
-                    };
-
-                    req.set_peer_addr(stream.peer_addr().ok());
-                    req.set_local_addr(stream.local_addr().ok());
+    /// Get a connection for a request to the `https` URL `url` from its pool, racing the
+    /// addresses of `route`.
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    async fn pooled_https(
+        &self,
+        url: &Url,
+        route: &Route,
+    ) -> Result<Object<Pooled<TlsStream<TcpStream>>, Error>, Error> {
+        let tunnel = route.tunnel.as_ref();
+        let delay = self.config.happy_eyeballs_delay;
+        happy_eyeballs::race(route.addrs.clone(), delay, |addr| {
+            let key = PoolKey::new(url, route.proxy.as_ref(), addr);
+            // The pool is cloned out of its entry, which would lock its part of the map while
+            // awaited, and which the reaper may remove once released.
+            let pool = self
+                .https_pools
+                .entry(key)
+                .or_insert_with(|| {
+                    let conns = Arc::new(Conns::new(self.released.clone()));
+                    let manager = TlsConnection::new(
+                        route.host.clone(),
+                        addr,
+                        tunnel.cloned(),
+                        self.config.clone(),
+                        self.connector.clone(),
+                        conns.clone(),
+                    );
+                    ConnPool::new(manager, conns, self.config.clone())
+                })
+                .clone();
+            self.start_reaper();
 
-                    let tcp_conn = client::connect(TcpConnWrapper::new(stream), req);
-                    return if let Some(timeout) = self.config.timeout {
-                        async_std::future::timeout(timeout, tcp_conn).await?
-                    } else {
-                        tcp_conn.await
-                    };
-                }
-                #[cfg(any(feature = "native-tls", feature = "rustls"))]
-                "https" => {
-                    let pool_ref = if let Some(pool_ref) = self.https_pools.get(&addr) {
-                        pool_ref
-                    } else {
-                        let manager = TlsConnection::new(host.clone(), addr, self.config.clone());
-                        let pool = Pool::<TlsStream<TcpStream>, Error>::new(
-                            manager,
-                            self.config.max_connections_per_host,
-                        );
-                        self.https_pools.insert(addr, pool);
-                        self.https_pools.get(&addr).unwrap()
-                    };
-
-                    // Deadlocks are prevented by cloning an inner pool Arc and dropping the original locking reference before we await.
-                    let pool = pool_ref.clone();
-                    std::mem::drop(pool_ref);
-
-                    let stream = match pool.get().await {
-                        Ok(s) => s,
-                        Err(_) if has_another_addr => continue,
-                        Err(e) => return Err(Error::from_str(400, e.to_string())),
-                    };
-
-                    req.set_peer_addr(stream.get_ref().peer_addr().ok());
-                    req.set_local_addr(stream.get_ref().local_addr().ok());
-
-                    let tls_conn = client::connect(TlsConnWrapper::new(stream), req);
-                    return if let Some(timeout) = self.config.timeout {
-                        async_std::future::timeout(timeout, tls_conn).await?
-                    } else {
-                        tls_conn.await
-                    };
-                }
-                _ => unreachable!(),
+            async move {
+                pool.get().await.map_err(|e| match e {
+                    PoolError::Backend(e) => classify(e, ClientErrorKind::Connect),
+                    e => pool_error(e),
+                })
             }
+        })
+        .await
+    }
+
+    /// Send a request over a pooled (or, without keep-alive, a new) connection to a Unix domain
+    /// socket.
+    #[cfg(unix)]
+    async fn dispatch_unix(&self, path: PathBuf, mut req: Request) -> Result<Response, Error> {
+        req.set_peer_addr(Some(path.display()));
+
+        if !self.config.http_keep_alive {
+            let stream = unix::connect(&path, &self.config).await?;
+            return self.send_over(stream, req, false).await;
         }
 
-        Err(Error::from_str(
-            StatusCode::BadRequest,
-            "missing valid address",
-        ))
+        // The pool is cloned out of its entry, which would lock its part of the map while
+        // awaited, and which the reaper may remove once released.
+        let pool = self
+            .unix_pools
+            .entry(path.clone())
+            .or_insert_with(|| {
+                let conns = Arc::new(Conns::new(self.released.clone()));
+                let manager = UnixConnection::new(path.clone(), self.config.clone(), conns.clone());
+                ConnPool::new(manager, conns, self.config.clone())
+            })
+            .clone();
+        self.start_reaper();
+
+        let stream = pool.get().await.map_err(|e| match e {
+            PoolError::Backend(e) => ClientError::from_io(e, ClientErrorKind::Connect).into_error(),
+            e => pool_error(e),
+        })?;
+        self.send_over(UnixConnWrapper::new(stream), req, false)
+            .await
     }
 
-    /// Override the existing configuration with new configuration.
-    ///
-    /// Config options may not impact existing connections.
-    fn set_config(&mut self, config: Config) -> http_types::Result<()> {
-        #[cfg(features = "h1_client")]
-        assert!(config.max_connections_per_host > 0, "max_connections_per_host with h1_client must be greater than zero or it will deadlock!");
+    /// Start reaping the pools in the background, when `Config` retires idle or old
+    /// connections. Only the first call does anything.
+    fn start_reaper(&self) {
+        let period = match pool::reap_period(&self.config) {
+            Some(period) => period,
+            None => return,
+        };
+        self.reaper.call_once(|| {
+            let pools = self.pool_maps().iter().map(Arc::downgrade).collect();
+            pool::spawn_reaper(pools, period);
+        });
+    }
 
-        self.config = Arc::new(config);
+    /// The maps of pools of each kind of connection.
+    fn pool_maps(&self) -> Vec<Arc<dyn PoolMap>> {
+        let mut pools: Vec<Arc<dyn PoolMap>> = vec![self.http_pools.clone()];
+        #[cfg(any(feature = "native-tls", feature = "rustls"))]
+        pools.push(self.https_pools.clone());
+        #[cfg(unix)]
+        pools.push(self.unix_pools.clone());
+        pools
+    }
 
-        Ok(())
+    /// Send `req` over an open connection, within the response header timeout.
+    async fn send_over<C>(
+        &self,
+        conn: C,
+        req: Request,
+        absolute_form: bool,
+    ) -> Result<Response, Error>
+    where
+        C: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static,
+    {
+        if absolute_form {
+            let conn = AbsoluteForm::new(conn, req.method(), req.url());
+            self.send_over_direct(conn, req).await
+        } else {
+            self.send_over_direct(conn, req).await
+        }
     }
 
-    /// Get the current configuration.
-    fn config(&self) -> &Config {
-        &*self.config
+    async fn send_over_direct<C>(&self, conn: C, req: Request) -> Result<Response, Error>
+    where
+        C: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static,
+    {
+        let conn = client::connect(conn, req);
+        let duration = self.config.response_header_timeout.or(self.config.timeout);
+        timeout::timeout(TimeoutKind::ResponseHeader, duration, conn)
+            .await?
+            .map_err(|e| {
+                // Anything but a failed connection is a malformed response.
+                let kind = match e.downcast_ref::<std::io::Error>() {
+                    Some(_) => ClientErrorKind::Io,
+                    None => ClientErrorKind::Protocol,
+                };
+                classify(e, kind)
+            })
     }
 }
 
+fn shut_down() -> Error {
+    ClientError::new(ClientErrorKind::Other, "the client was shut down").into_error()
+}
+
+fn invalid_url(message: impl Into<String>) -> Error {
+    ClientError::new(ClientErrorKind::InvalidUrl, message.into()).into_error()
+}
+
+/// A failure of the pool itself, rather than of a connection in it.
+fn pool_error<E: std::fmt::Display>(err: PoolError<E>) -> Error {
+    ClientError::new(ClientErrorKind::Other, err.to_string()).into_error()
+}
+
+#[allow(clippy::infallible_try_from)]
 impl TryFrom<Config> for H1Client {
     type Error = Infallible;
 
     fn try_from(config: Config) -> Result<Self, Self::Error> {
-        #[cfg(features = "h1_client")]
+        #[cfg(feature = "h1_client")]
         assert!(config.max_connections_per_host > 0, "max_connections_per_host with h1_client must be greater than zero or it will deadlock!");
 
         Ok(Self {
-            http_pools: DashMap::new(),
+            http_pools: Arc::new(DashMap::new()),
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            https_pools: Arc::new(DashMap::new()),
+            #[cfg(unix)]
+            unix_pools: Arc::new(DashMap::new()),
+            reaper: Once::new(),
+            shut_down: AtomicBool::new(false),
+            released: Arc::new(Event::new()),
             #[cfg(any(feature = "native-tls", feature = "rustls"))]
-            https_pools: DashMap::new(),
+            connector: Connector::new(&config),
             config: Arc::new(config),
         })
     }
@@ -302,10 +710,13 @@ impl TryFrom<Config> for H1Client {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_servers::{spawn_keep_alive, spawn_origin, spawn_silent};
+    use crate::StaticResolver;
     use async_std::prelude::*;
     use async_std::task;
     use http_types::url::Url;
     use http_types::Result;
+    use std::sync::atomic::Ordering;
     use std::time::Duration;
 
     fn build_test_request(url: Url) -> Request {
@@ -357,4 +768,279 @@ mod tests {
         assert_eq!(*json_val.get("data").unwrap(), serde_json::json!("hello"));
         Ok(())
     }
+
+    #[async_std::test]
+    async fn reports_which_timeout_elapsed() -> Result<()> {
+        let addr = spawn_silent().await;
+        let timeout = Some(Duration::from_millis(100));
+
+        #[cfg_attr(
+            not(any(feature = "native-tls", feature = "rustls")),
+            allow(unused_mut)
+        )]
+        let mut cases = vec![
+            (
+                Config::new().set_response_header_timeout(timeout),
+                "http",
+                TimeoutKind::ResponseHeader,
+            ),
+            (
+                Config::new().set_total_timeout(timeout),
+                "http",
+                TimeoutKind::Total,
+            ),
+        ];
+        #[cfg(any(feature = "native-tls", feature = "rustls"))]
+        {
+            // The silent server also stands in for a proxy which never answers `CONNECT`
+            // requests.
+            let proxy = Proxy::new(format!("http://{}", addr))?;
+            cases.extend(vec![
+                (
+                    Config::new().set_tls_handshake_timeout(timeout),
+                    "https",
+                    TimeoutKind::TlsHandshake,
+                ),
+                (
+                    Config::new()
+                        .set_connect_timeout(timeout)
+                        .set_proxy(Some(proxy)),
+                    "https",
+                    TimeoutKind::Connect,
+                ),
+            ]);
+        }
+
+        for (config, scheme, kind) in cases {
+            let client = H1Client::try_from(config)?;
+            let url = Url::parse(&format!("{}://localhost:{}/", scheme, addr.port()))?;
+            let req = Request::new(http_types::Method::Get, url);
+            let err = client.send(req).await.unwrap_err();
+            assert_eq!(crate::TimeoutError::of(&err).unwrap().kind(), kind);
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn resolves_with_config_resolver() -> Result<()> {
+        let origin = spawn_origin().await;
+        let resolver = StaticResolver::new().with_host("origin.test", vec![origin.ip()]);
+        let client = H1Client::try_from(Config::new().set_resolver(Some(Arc::new(resolver))))?;
+
+        let url = Url::parse(&format!("http://origin.test:{}/hello", origin.port()))?;
+        let mut res = client
+            .send(Request::new(http_types::Method::Get, url))
+            .await?;
+        assert_eq!(res.body_string().await?, "GET /hello HTTP/1.1");
+
+        let url = Url::parse(&format!("http://other.test:{}/", origin.port()))?;
+        let err = client
+            .send(Request::new(http_types::Method::Get, url))
+            .await
+            .unwrap_err();
+        assert_eq!(ClientError::of(&err).unwrap().kind(), ClientErrorKind::Dns);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn falls_back_to_other_addresses() -> Result<()> {
+        // The origin only listens on IPv4, so connecting to it over IPv6 fails.
+        let origin = spawn_origin().await;
+        let ips = vec!["::1".parse()?, origin.ip()];
+        let resolver = StaticResolver::new().with_host("origin.test", ips);
+        let url = Url::parse(&format!("http://origin.test:{}/", origin.port()))?;
+
+        for keep_alive in [true, false] {
+            let config = Config::new()
+                .set_resolver(Some(Arc::new(resolver.clone())))
+                .set_http_keep_alive(keep_alive)
+                .set_happy_eyeballs_delay(Some(Duration::from_secs(10)));
+            let client = H1Client::try_from(config)?;
+            let req = Request::new(http_types::Method::Get, url.clone());
+            let mut res = client.send(req).timeout(Duration::from_secs(1)).await??;
+            assert_eq!(res.body_string().await?, "GET / HTTP/1.1");
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn reports_what_kind_of_failure() -> Result<()> {
+        // Nothing listens on a port once its listener is dropped.
+        let port = async_std::net::TcpListener::bind(("127.0.0.1", 0))
+            .await?
+            .local_addr()?
+            .port();
+
+        let cases = vec![
+            (
+                "http://nonexistent.invalid/".to_string(),
+                ClientErrorKind::Dns,
+            ),
+            (
+                format!("http://127.0.0.1:{}/", port),
+                ClientErrorKind::Connect,
+            ),
+            (
+                "ftp://example.com/".to_string(),
+                ClientErrorKind::InvalidUrl,
+            ),
+        ];
+        for (url, kind) in cases {
+            let req = Request::new(http_types::Method::Get, Url::parse(&url)?);
+            let err = H1Client::new().send(req).await.unwrap_err();
+            assert_eq!(ClientError::of(&err).unwrap().kind(), kind, "{}", url);
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn retires_connections() -> Result<()> {
+        let cases = vec![
+            Config::new().set_max_requests_per_connection(Some(2)),
+            Config::new().set_pool_max_lifetime(Some(Duration::from_millis(100))),
+            Config::new().set_pool_idle_timeout(Some(Duration::from_millis(100))),
+        ];
+        for config in cases {
+            let (addr, connections) = spawn_keep_alive().await;
+            let client = H1Client::try_from(config.clone())?;
+            let url = Url::parse(&format!("http://{}/", addr))?;
+            for _ in 0..2 {
+                let mut res = client
+                    .send(Request::new(http_types::Method::Get, url.clone()))
+                    .await?;
+                assert_eq!(res.body_string().await?, "GET / HTTP/1.1");
+            }
+            assert_eq!(connections.accepted.load(Ordering::SeqCst), 1);
+
+            task::sleep(Duration::from_millis(150)).await;
+            let mut res = client
+                .send(Request::new(http_types::Method::Get, url))
+                .await?;
+            assert_eq!(res.body_string().await?, "GET / HTTP/1.1");
+            assert_eq!(
+                connections.accepted.load(Ordering::SeqCst),
+                2,
+                "{:?}",
+                config
+            );
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn reaps_idle_connections() -> Result<()> {
+        let (addr, connections) = spawn_keep_alive().await;
+        let config = Config::new().set_pool_idle_timeout(Some(Duration::from_millis(100)));
+        let client = H1Client::try_from(config)?;
+
+        let url = Url::parse(&format!("http://{}/", addr))?;
+        let mut res = client
+            .send(Request::new(http_types::Method::Get, url))
+            .await?;
+        assert_eq!(res.body_string().await?, "GET / HTTP/1.1");
+        assert_eq!(connections.open.load(Ordering::SeqCst), 1);
+        assert_eq!(client.http_pools.len(), 1);
+
+        task::sleep(Duration::from_millis(300)).await;
+        assert_eq!(connections.open.load(Ordering::SeqCst), 0);
+        assert!(client.http_pools.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn reaps_zero_timeouts_at_a_bounded_rate() {
+        let config = Config::new().set_pool_idle_timeout(Some(Duration::ZERO));
+        let period = pool::reap_period(&config).unwrap();
+        assert!(period > Duration::ZERO);
+
+        let config = Config::new()
+            .set_pool_idle_timeout(None)
+            .set_pool_max_lifetime(Some(Duration::ZERO));
+        assert_eq!(pool::reap_period(&config), Some(period));
+    }
+
+    #[async_std::test]
+    async fn reports_pool_stats() -> Result<()> {
+        let (addr, _) = spawn_keep_alive().await;
+        let config = Config::new().set_max_requests_per_connection(Some(2));
+        let client = H1Client::try_from(config)?;
+        let url = Url::parse(&format!("http://{}/", addr))?;
+        for _ in 0..3 {
+            let mut res = client
+                .send(Request::new(http_types::Method::Get, url.clone()))
+                .await?;
+            assert_eq!(res.body_string().await?, "GET / HTTP/1.1");
+        }
+
+        let stats = client.pool_stats();
+        assert_eq!(stats.len(), 1);
+        let stats = &stats[0];
+        assert_eq!(stats.origin, format!("http://127.0.0.1:{}", addr.port()));
+        assert_eq!(stats.addr, addr);
+        assert_eq!((stats.size, stats.available, stats.waiting), (1, 1, 0));
+        assert_eq!((stats.created, stats.recycled, stats.evicted), (2, 1, 1));
+
+        let debug = format!("{:?}", client);
+        assert!(debug.contains("http_pools: {PoolKey"), "{}", debug);
+        #[cfg(any(feature = "native-tls", feature = "rustls"))]
+        assert!(debug.contains("https_pools: {}"), "{}", debug);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn warms_up_and_closes_idle() -> Result<()> {
+        let (addr, connections) = spawn_keep_alive().await;
+        let client = H1Client::new();
+        let url = Url::parse(&format!("http://{}/", addr))?;
+
+        client.warm_up(&url, 3).await?;
+        let stats = &client.pool_stats()[0];
+        assert_eq!((stats.size, stats.available, stats.created), (3, 3, 3));
+
+        let mut res = client
+            .send(Request::new(http_types::Method::Get, url))
+            .await?;
+        assert_eq!(res.body_string().await?, "GET / HTTP/1.1");
+        assert_eq!(client.pool_stats()[0].created, 3);
+
+        client.close_idle().await;
+        assert!(client.pool_stats().is_empty());
+        task::sleep(Duration::from_millis(50)).await;
+        assert_eq!(connections.accepted.load(Ordering::SeqCst), 3);
+        assert_eq!(connections.open.load(Ordering::SeqCst), 0);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn shuts_down() -> Result<()> {
+        let (addr, connections) = spawn_keep_alive().await;
+        let client = H1Client::new();
+        let url = Url::parse(&format!("http://{}/", addr))?;
+
+        let res = client
+            .send(Request::new(http_types::Method::Get, url.clone()))
+            .await?;
+        let deadline = Instant::now() + Duration::from_millis(100);
+        assert!(!client.shutdown(deadline).await);
+        assert!(Instant::now() >= deadline);
+
+        drop(res);
+        assert!(
+            client
+                .shutdown(Instant::now() + Duration::from_secs(1))
+                .await
+        );
+        task::sleep(Duration::from_millis(50)).await;
+        assert_eq!(connections.open.load(Ordering::SeqCst), 0);
+
+        let err = client
+            .send(Request::new(http_types::Method::Get, url))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            ClientError::of(&err).unwrap().kind(),
+            ClientErrorKind::Other
+        );
+        Ok(())
+    }
 }
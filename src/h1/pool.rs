@@ -0,0 +1,464 @@
+//! Pools of connections, and retiring the connections in them.
+//!
+//! Connections are retired once they have been idle for `Config::pool_idle_timeout`, are older
+//! than `Config::pool_max_lifetime`, or have been sent `Config::max_requests_per_connection`
+//! requests. The managers of the pools check this when recycling a connection for a request, and
+//! a reaper task checks the idle connections in place, so that those no request asks for are
+//! closed too.
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::hash::Hash;
+use std::io;
+use std::net::SocketAddr;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use async_std::task;
+use async_trait::async_trait;
+use dashmap::DashMap;
+use deadpool::managed::{Manager, Object, Pool, PoolError, RecycleError, RecycleResult};
+use event_listener::Event;
+use futures::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use futures::task::{noop_waker_ref, Context, Poll};
+
+use crate::Config;
+
+/// A pooled connection, along with what is needed to tell when to retire it.
+///
+/// While no request uses it, the connection itself is parked with the other idle connections of
+/// its pool, where the reaper can close it without checking it out of the pool.
+pub(crate) struct Pooled<T> {
+    id: u64,
+    conn: Option<T>,
+    age: Age,
+    in_use: bool,
+    conns: Arc<Conns<T>>,
+}
+
+impl<T> Pooled<T> {
+    /// Add a newly opened connection to the pool `conns` belong to.
+    pub(crate) fn new(conn: T, conns: &Arc<Conns<T>>) -> Self {
+        conns.counters.open.fetch_add(1, Ordering::Relaxed);
+        let id = conns.counters.created.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        Self {
+            id,
+            conn: Some(conn),
+            age: Age {
+                created: now,
+                idle_since: now,
+                requests: 0,
+            },
+            in_use: false,
+            conns: conns.clone(),
+        }
+    }
+
+    /// Record the start of a request over the connection.
+    pub(crate) fn acquire(&mut self) {
+        self.set_in_use(true);
+    }
+
+    /// Record the end of a request over the connection, as it goes back into its pool.
+    pub(crate) fn release(&mut self) {
+        self.age.requests += 1;
+        self.age.idle_since = Instant::now();
+        self.set_in_use(false);
+        self.park();
+    }
+
+    /// Park the connection with the idle connections of its pool, until it is recycled.
+    pub(crate) fn park(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            let idle = Idle {
+                conn,
+                age: self.age,
+            };
+            self.conns.idle.lock().unwrap().insert(self.id, idle);
+            self.conns.released.notify(usize::MAX);
+        }
+    }
+
+    fn set_in_use(&mut self, in_use: bool) {
+        let counters = &self.conns.counters;
+        match (self.in_use, in_use) {
+            (false, true) => counters.in_use.fetch_add(1, Ordering::Relaxed),
+            (true, false) => counters.in_use.fetch_sub(1, Ordering::Relaxed),
+            _ => return,
+        };
+        self.in_use = in_use;
+    }
+
+    /// Check that the connection may still be used, counting it as recycled if so, and as
+    /// evicted if not.
+    pub(crate) fn recycle<E>(&mut self, config: &Config) -> RecycleResult<E>
+    where
+        T: AsyncRead + Unpin,
+        E: From<io::Error>,
+    {
+        if self.conn.is_none() {
+            // The reaper counted the connection as evicted when it closed it.
+            let idle = self.conns.idle.lock().unwrap().remove(&self.id);
+            match idle {
+                Some(idle) => self.conn = Some(idle.conn),
+                None => return Err(RecycleError::Message("connection was reaped".to_string())),
+            }
+        }
+
+        let res = match self.age.retirement(config) {
+            Some(reason) => Err(RecycleError::Message(reason.to_string())),
+            None => self.probe(),
+        };
+        match res {
+            Ok(()) => self.conns.counters.recycled.fetch_add(1, Ordering::Relaxed),
+            Err(_) => self.conns.counters.evicted.fetch_add(1, Ordering::Relaxed),
+        };
+        res
+    }
+
+    /// Fail when the peer has closed the connection, or sent data no request asked for.
+    fn probe<E: From<io::Error>>(&mut self) -> RecycleResult<E>
+    where
+        T: AsyncRead + Unpin,
+    {
+        let mut buf = [0; 4];
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        match Pin::new(&mut **self).poll_read(&mut cx, &mut buf) {
+            Poll::Ready(Err(error)) => Err(E::from(error).into()),
+            Poll::Ready(Ok(0)) => Err(E::from(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection appeared to be closed (EoF)",
+            ))
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<T> Deref for Pooled<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.conn.as_ref().expect("pooled connection is parked")
+    }
+}
+
+impl<T> DerefMut for Pooled<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.conn.as_mut().expect("pooled connection is parked")
+    }
+}
+
+impl<T> Drop for Pooled<T> {
+    fn drop(&mut self) {
+        self.set_in_use(false);
+        // A parked connection the reaper has closed was counted as closed then.
+        let parked = self.conns.idle.lock().unwrap().remove(&self.id);
+        if self.conn.is_some() || parked.is_some() {
+            self.conns.closed(1);
+        }
+    }
+}
+
+/// When a connection was opened, when it was last used, and how many requests it was sent.
+#[derive(Clone, Copy, Debug)]
+struct Age {
+    created: Instant,
+    idle_since: Instant,
+    requests: usize,
+}
+
+impl Age {
+    /// Why `config` says the connection should be retired, if it does.
+    fn retirement(&self, config: &Config) -> Option<&'static str> {
+        if matches!(config.pool_idle_timeout, Some(timeout) if self.idle_since.elapsed() >= timeout)
+        {
+            return Some("connection was idle for too long");
+        }
+        if matches!(config.pool_max_lifetime, Some(lifetime) if self.created.elapsed() >= lifetime)
+        {
+            return Some("connection was open for too long");
+        }
+        if matches!(config.max_requests_per_connection, Some(max) if self.requests >= max) {
+            return Some("connection was sent too many requests");
+        }
+        None
+    }
+}
+
+/// A parked connection.
+struct Idle<T> {
+    conn: T,
+    age: Age,
+}
+
+/// What a pool and the connections in it share: their counts, the parked connections, and the
+/// event of a connection being released or closed.
+pub(crate) struct Conns<T> {
+    counters: Counters,
+    idle: Mutex<HashMap<u64, Idle<T>>>,
+    released: Arc<Event>,
+}
+
+impl<T> Conns<T> {
+    /// Create the shared state of a new pool, whose connections notify `released` as requests
+    /// finish with them.
+    pub(crate) fn new(released: Arc<Event>) -> Self {
+        Self {
+            counters: Counters::default(),
+            idle: Mutex::new(HashMap::new()),
+            released,
+        }
+    }
+
+    /// Take the parked connections `retire` picks out of the pool, counting them as evicted.
+    fn take_idle(&self, mut retire: impl FnMut(&Age) -> bool) -> Vec<T> {
+        let taken: Vec<_> = {
+            let mut idle = self.idle.lock().unwrap();
+            let ids: Vec<_> = idle
+                .iter()
+                .filter(|(_, idle)| retire(&idle.age))
+                .map(|(id, _)| *id)
+                .collect();
+            ids.iter()
+                .filter_map(|id| idle.remove(id))
+                .map(|idle| idle.conn)
+                .collect()
+        };
+        let n = taken.len();
+        self.counters.evicted.fetch_add(n as u64, Ordering::Relaxed);
+        self.closed(n);
+        taken
+    }
+
+    /// Count `n` connections as closed.
+    fn closed(&self, n: usize) {
+        if n > 0 {
+            self.counters.open.fetch_sub(n, Ordering::Relaxed);
+            self.released.notify(usize::MAX);
+        }
+    }
+}
+
+impl<T> Debug for Conns<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.counters.fmt(f)
+    }
+}
+
+/// What has become of the connections of a pool.
+///
+/// The status of a deadpool `Pool` loses count of connections which fail to be recycled, so
+/// pools keep their own counts.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    /// Connections which are open, whether in use or idle in the pool.
+    open: AtomicUsize,
+    /// Connections which requests are being sent over.
+    in_use: AtomicUsize,
+    /// Requests waiting on the pool for a connection, including one being opened for them.
+    waiting: AtomicUsize,
+    created: AtomicU64,
+    recycled: AtomicU64,
+    evicted: AtomicU64,
+}
+
+/// The state of a pool of connections, from `H1Client::pool_stats`.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoolStats {
+    /// The origin which requests over the connections are sent to, as `scheme://host:port`.
+    pub origin: String,
+    /// The address the connections are open to, which is that of the proxy in front of the origin
+    /// when there is one.
+    pub addr: SocketAddr,
+    /// Open connections, whether in use or idle.
+    pub size: usize,
+    /// Idle connections, waiting for a request.
+    pub available: usize,
+    /// Requests waiting for a connection, including those a connection is being opened for.
+    pub waiting: usize,
+    /// The most connections the pool opens at once, from `Config::max_connections_per_host`.
+    pub max_size: usize,
+    /// Connections opened since the pool was created.
+    pub created: u64,
+    /// Times an idle connection was checked, and found fit to be used again.
+    pub recycled: u64,
+    /// Connections closed instead of being handed out again, because they were closed by the
+    /// peer or were retired.
+    pub evicted: u64,
+}
+
+/// A pool of connections to one origin.
+pub(crate) struct ConnPool<T, E> {
+    pool: Pool<Pooled<T>, E>,
+    conns: Arc<Conns<T>>,
+    config: Arc<Config>,
+}
+
+impl<T, E> ConnPool<T, E> {
+    /// Create a new pool of connections, which `manager` adds to `conns`, retired as `config`
+    /// says.
+    pub(crate) fn new(
+        manager: impl Manager<Pooled<T>, E> + Send + Sync + 'static,
+        conns: Arc<Conns<T>>,
+        config: Arc<Config>,
+    ) -> Self {
+        Self {
+            pool: Pool::new(manager, config.max_connections_per_host),
+            conns,
+            config,
+        }
+    }
+
+    /// Get a connection from the pool, opening one if there are none idle.
+    pub(crate) async fn get(&self) -> Result<Object<Pooled<T>, E>, PoolError<E>> {
+        self.conns.counters.waiting.fetch_add(1, Ordering::Relaxed);
+        let _waiting = Waiting(&self.conns.counters);
+        self.pool.get().await
+    }
+
+    /// The state of the pool of connections to `origin` at `addr`.
+    pub(crate) fn stats(&self, origin: String, addr: SocketAddr) -> PoolStats {
+        let counters = &self.conns.counters;
+        let size = counters.open.load(Ordering::Relaxed);
+        PoolStats {
+            origin,
+            addr,
+            size,
+            available: size.saturating_sub(counters.in_use.load(Ordering::Relaxed)),
+            waiting: counters.waiting.load(Ordering::Relaxed),
+            max_size: self.pool.status().max_size,
+            created: counters.created.load(Ordering::Relaxed),
+            recycled: counters.recycled.load(Ordering::Relaxed),
+            evicted: counters.evicted.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Whether the pool holds no connections, and no request waits on it.
+    fn is_unused(&self) -> bool {
+        let counters = &self.conns.counters;
+        counters.open.load(Ordering::Relaxed) == 0 && counters.waiting.load(Ordering::Relaxed) == 0
+    }
+}
+
+impl<T, E> Clone for ConnPool<T, E> {
+    fn clone(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            conns: self.conns.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<T, E> Debug for ConnPool<T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnPool")
+            .field("counters", &self.conns)
+            .field("max_size", &self.pool.status().max_size)
+            .finish()
+    }
+}
+
+/// Counts a request as no longer waiting on a pool, once it has a connection or gives up.
+struct Waiting<'a>(&'a Counters);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.waiting.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A map of pools, whose idle connections can be closed.
+#[async_trait]
+pub(crate) trait PoolMap: Send + Sync {
+    /// Close the idle connections of each pool which should be retired, and remove the pools
+    /// left unused.
+    async fn reap(&self);
+
+    /// Close the idle connections of each pool.
+    async fn close_idle(&self);
+
+    /// The number of connections open in all the pools.
+    fn open(&self) -> usize;
+}
+
+#[async_trait]
+impl<K, T, E> PoolMap for DashMap<K, ConnPool<T, E>>
+where
+    K: Eq + Hash + Send + Sync,
+    T: AsyncWrite + Unpin + Send + 'static,
+    E: Send + 'static,
+{
+    async fn reap(&self) {
+        for pool in pools(self) {
+            let retired = pool
+                .conns
+                .take_idle(|age| age.retirement(&pool.config).is_some());
+            close(retired).await;
+        }
+        self.retain(|_, pool| !pool.is_unused());
+    }
+
+    async fn close_idle(&self) {
+        for pool in pools(self) {
+            close(pool.conns.take_idle(|_| true)).await;
+        }
+        self.retain(|_, pool| !pool.is_unused());
+    }
+
+    fn open(&self) -> usize {
+        self.iter()
+            .map(|pool| pool.conns.counters.open.load(Ordering::Relaxed))
+            .sum()
+    }
+}
+
+/// The pools in `map`, which references into must not be held across awaits.
+fn pools<K: Eq + Hash, V: Clone>(map: &DashMap<K, V>) -> Vec<V> {
+    map.iter().map(|pool| pool.value().clone()).collect()
+}
+
+/// Close `conns` cleanly.
+async fn close<T: AsyncWrite + Unpin>(conns: Vec<T>) {
+    for mut conn in conns {
+        // The connection is dropped either way.
+        let _ = conn.close().await;
+    }
+}
+
+/// How often the reaper checks the pools of a client with `config`, if it needs to at all.
+///
+/// Connections are checked when taken from a pool as well, so very short timeouts only need the
+/// reaper now and then.
+pub(crate) fn reap_period(config: &Config) -> Option<Duration> {
+    const MIN_REAP_PERIOD: Duration = Duration::from_millis(100);
+
+    let shortest = match (config.pool_idle_timeout, config.pool_max_lifetime) {
+        (Some(idle), Some(lifetime)) => idle.min(lifetime),
+        (idle, lifetime) => idle.or(lifetime)?,
+    };
+    Some((shortest / 2).max(MIN_REAP_PERIOD))
+}
+
+/// Reap `pools` every `period`, until they are dropped.
+pub(crate) fn spawn_reaper(pools: Vec<Weak<dyn PoolMap>>, period: Duration) {
+    task::spawn(async move {
+        loop {
+            task::sleep(period).await;
+            let pools: Vec<_> = pools.iter().filter_map(Weak::upgrade).collect();
+            if pools.is_empty() {
+                return;
+            }
+            for pools in pools {
+                pools.reap().await;
+            }
+        }
+    });
+}
@@ -0,0 +1,217 @@
+//! Sending requests through proxies.
+
+use std::io;
+use std::pin::Pin;
+
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::ready;
+use futures::task::{Context, Poll};
+use http_types::url::Url;
+use http_types::Method;
+
+enum State {
+    /// Discarding the request line written by `async_h1`.
+    Skipping,
+    /// Writing the absolute-form request line.
+    Writing,
+    /// Passing everything after the request line through unchanged.
+    Passthrough,
+}
+
+/// A connection to a proxy, which replaces the origin-form request line written by `async_h1` with
+/// the absolute-form one a proxy expects.
+pub(crate) struct AbsoluteForm<S> {
+    inner: S,
+    request_line: Vec<u8>,
+    written: usize,
+    state: State,
+}
+
+impl<S> AbsoluteForm<S> {
+    pub(crate) fn new(inner: S, method: Method, url: &Url) -> Self {
+        let mut url = url.clone();
+        url.set_fragment(None);
+        // The URL has a host, so removing its credentials cannot fail.
+        let _ = url.set_username("");
+        let _ = url.set_password(None);
+
+        Self {
+            inner,
+            request_line: format!("{} {} HTTP/1.1\r\n", method, url).into_bytes(),
+            written: 0,
+            state: State::Skipping,
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AbsoluteForm<S> {
+    fn poll_write_request_line(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.request_line.len() {
+            let n = ready!(
+                Pin::new(&mut self.inner).poll_write(cx, &self.request_line[self.written..])
+            )?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.state = State::Passthrough;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for AbsoluteForm<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for AbsoluteForm<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            match this.state {
+                State::Skipping => {
+                    return match buf.iter().position(|&b| b == b'\n') {
+                        Some(end) => {
+                            this.state = State::Writing;
+                            Poll::Ready(Ok(end + 1))
+                        }
+                        None => Poll::Ready(Ok(buf.len())),
+                    };
+                }
+                State::Writing => ready!(this.poll_write_request_line(cx))?,
+                State::Passthrough => return Pin::new(&mut this.inner).poll_write(cx, buf),
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let State::Writing = this.state {
+            ready!(this.poll_write_request_line(cx))?;
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let State::Writing = this.state {
+            ready!(this.poll_write_request_line(cx))?;
+        }
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::tunnel::Tunnel;
+    use crate::test_servers::{spawn_origin, spawn_proxy, spawn_socks5};
+    use crate::{ClientError, ClientErrorKind, Config, HttpClient, Proxy, Request, SystemResolver};
+    use async_std::net::TcpStream;
+    use std::sync::Arc;
+
+    #[async_std::test]
+    async fn sends_absolute_form_through_proxy() -> http_types::Result<()> {
+        let origin = spawn_origin().await;
+        let (proxy, heads) = spawn_proxy().await;
+
+        let mut client = crate::h1::H1Client::new();
+        client.set_config(
+            Config::new().set_proxy(Some(Proxy::new(format!("http://user:pass@{}", proxy))?)),
+        )?;
+
+        let url = format!("http://{}/hello?x=1#fragment", origin);
+        let req = Request::new(Method::Get, Url::parse(&url)?);
+        let mut res = client.send(req).await?;
+        assert_eq!(res.body_string().await?, "GET /hello?x=1 HTTP/1.1");
+
+        let heads = heads.lock().unwrap();
+        assert!(heads[0].starts_with(&format!("GET http://{}/hello?x=1 HTTP/1.1\r\n", origin)));
+        assert!(heads[0].contains("proxy-authorization: Basic dXNlcjpwYXNz\r\n"));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn establishes_connect_tunnel() -> http_types::Result<()> {
+        let origin = spawn_origin().await;
+        let (proxy_addr, heads) = spawn_proxy().await;
+
+        let url = Url::parse(&format!("http://{}/", origin))?;
+        let proxy = Proxy::new(format!("http://user:pass@{}", proxy_addr))?;
+        let tunnel = Tunnel::new(proxy, &url, Arc::new(SystemResolver)).unwrap();
+        let mut stream = TcpStream::connect(proxy_addr).await?;
+        tunnel.establish(&mut stream).await?;
+
+        let req = Request::new(Method::Get, url);
+        let mut res = async_h1::client::connect(stream, req).await?;
+        assert_eq!(res.body_string().await?, "GET / HTTP/1.1");
+
+        let heads = heads.lock().unwrap();
+        assert!(heads[0].starts_with(&format!("CONNECT {} HTTP/1.1\r\n", origin)));
+        assert!(heads[0].contains("proxy-authorization: Basic dXNlcjpwYXNz\r\n"));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn sends_requests_through_socks5() -> http_types::Result<()> {
+        let origin = spawn_origin().await;
+        let (proxy, hosts) = spawn_socks5().await;
+        let url = Url::parse(&format!("http://localhost:{}/hello", origin.port()))?;
+
+        for (scheme, host) in [("socks5h", "localhost"), ("socks5", "127.0.0.1")] {
+            let mut client = crate::h1::H1Client::new();
+            client.set_config(Config::new().set_proxy(Some(Proxy::new(format!(
+                "{}://user:pass@{}",
+                scheme, proxy
+            ))?)))?;
+
+            let mut res = client.send(Request::new(Method::Get, url.clone())).await?;
+            assert_eq!(res.body_string().await?, "GET /hello HTTP/1.1");
+            assert_eq!(hosts.lock().unwrap().pop().unwrap(), host);
+        }
+
+        let mut client = crate::h1::H1Client::new();
+        client.set_config(
+            Config::new().set_proxy(Some(Proxy::new(format!("socks5h://user:wrong@{}", proxy))?)),
+        )?;
+        let err = client
+            .send(Request::new(Method::Get, url))
+            .await
+            .unwrap_err();
+        let err = ClientError::of(&err).unwrap();
+        assert_eq!(err.kind(), ClientErrorKind::Proxy);
+        let err = err.get_ref().downcast_ref::<io::Error>().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn pools_connections_per_proxy() -> http_types::Result<()> {
+        let origin = spawn_origin().await;
+        let (proxy, hosts) = spawn_socks5().await;
+        let url = Url::parse(&format!("http://localhost:{}/hello", origin.port()))?;
+
+        // Both proxies are at the same address, but a connection through one is not reused once
+        // the config names the other.
+        let mut client = crate::h1::H1Client::new();
+        for (scheme, host) in [("socks5", "127.0.0.1"), ("socks5h", "localhost")] {
+            let proxy = Proxy::new(format!("{}://user:pass@{}", scheme, proxy))?;
+            client.set_config(Config::new().set_proxy(Some(proxy)))?;
+
+            let mut res = client.send(Request::new(Method::Get, url.clone())).await?;
+            assert_eq!(res.body_string().await?, "GET /hello HTTP/1.1");
+            assert_eq!(hosts.lock().unwrap().pop().as_deref(), Some(host));
+        }
+        Ok(())
+    }
+}
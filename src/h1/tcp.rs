@@ -8,37 +8,59 @@ use deadpool::managed::{Manager, Object, RecycleResult};
 use futures::io::{AsyncRead, AsyncWrite};
 use futures::task::{Context, Poll};
 
-use crate::Config;
+use super::pool::{Conns, Pooled};
+use crate::proxy::tunnel::Tunnel;
+use crate::timeout::timeout;
+use crate::{ClientError, ClientErrorKind, Config, TimeoutKind};
 
 #[derive(Clone)]
 #[cfg_attr(not(feature = "rustls"), derive(std::fmt::Debug))]
 pub(crate) struct TcpConnection {
     addr: SocketAddr,
+    tunnel: Option<Tunnel>,
     config: Arc<Config>,
+    conns: Arc<Conns<TcpStream>>,
 }
 
 impl TcpConnection {
-    pub(crate) fn new(addr: SocketAddr, config: Arc<Config>) -> Self {
-        Self { addr, config }
+    pub(crate) fn new(
+        addr: SocketAddr,
+        tunnel: Option<Tunnel>,
+        config: Arc<Config>,
+        conns: Arc<Conns<TcpStream>>,
+    ) -> Self {
+        Self {
+            addr,
+            tunnel,
+            config,
+            conns,
+        }
     }
 }
 
 pub(crate) struct TcpConnWrapper {
-    conn: Object<TcpStream, std::io::Error>,
+    conn: Object<Pooled<TcpStream>, std::io::Error>,
 }
 impl TcpConnWrapper {
-    pub(crate) fn new(conn: Object<TcpStream, std::io::Error>) -> Self {
+    pub(crate) fn new(mut conn: Object<Pooled<TcpStream>, std::io::Error>) -> Self {
+        conn.acquire();
         Self { conn }
     }
 }
 
+impl Drop for TcpConnWrapper {
+    fn drop(&mut self) {
+        self.conn.release();
+    }
+}
+
 impl AsyncRead for TcpConnWrapper {
     fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<Result<usize, std::io::Error>> {
-        Pin::new(&mut *self.conn).poll_read(cx, buf)
+        Pin::new(&mut **self.conn).poll_read(cx, buf)
     }
 }
 
@@ -48,42 +70,53 @@ impl AsyncWrite for TcpConnWrapper {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
-        Pin::new(&mut *self.conn).poll_write(cx, buf)
+        Pin::new(&mut **self.conn).poll_write(cx, buf)
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut *self.conn).poll_flush(cx)
+        Pin::new(&mut **self.conn).poll_flush(cx)
     }
 
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut *self.conn).poll_close(cx)
+        Pin::new(&mut **self.conn).poll_close(cx)
     }
 }
 
 #[async_trait]
-impl Manager<TcpStream, std::io::Error> for TcpConnection {
-    async fn create(&self) -> Result<TcpStream, std::io::Error> {
-        let tcp_stream = TcpStream::connect(self.addr).await?;
-
-        tcp_stream.set_nodelay(self.config.tcp_no_delay)?;
-
-        Ok(tcp_stream)
+impl Manager<Pooled<TcpStream>, std::io::Error> for TcpConnection {
+    async fn create(&self) -> Result<Pooled<TcpStream>, std::io::Error> {
+        let conn = connect(self.addr, self.tunnel.as_ref(), &self.config).await?;
+        Ok(Pooled::new(conn, &self.conns))
     }
 
-    async fn recycle(&self, conn: &mut TcpStream) -> RecycleResult<std::io::Error> {
-        let mut buf = [0; 4];
-        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
-
+    async fn recycle(&self, conn: &mut Pooled<TcpStream>) -> RecycleResult<std::io::Error> {
+        conn.recycle(&self.config)?;
         conn.set_nodelay(self.config.tcp_no_delay)?;
-
-        match Pin::new(conn).poll_read(&mut cx, &mut buf) {
-            Poll::Ready(Err(error)) => Err(error),
-            Poll::Ready(Ok(bytes)) if bytes == 0 => Err(std::io::Error::new(
-                std::io::ErrorKind::UnexpectedEof,
-                "connection appeared to be closed (EoF)",
-            )),
-            _ => Ok(()),
-        }?;
         Ok(())
     }
 }
+
+/// Open a TCP connection to `addr`, and on through `tunnel` when it is a proxy.
+pub(crate) async fn connect(
+    addr: SocketAddr,
+    tunnel: Option<&Tunnel>,
+    config: &Config,
+) -> Result<TcpStream, std::io::Error> {
+    let connect = async {
+        let mut tcp_stream = TcpStream::connect(addr)
+            .await
+            .map_err(|e| ClientError::from_io(e, ClientErrorKind::Connect))?;
+
+        tcp_stream.set_nodelay(config.tcp_no_delay)?;
+
+        if let Some(tunnel) = tunnel {
+            tunnel
+                .establish(&mut tcp_stream)
+                .await
+                .map_err(|e| ClientError::from_io(e, ClientErrorKind::Proxy))?;
+        }
+
+        Ok(tcp_stream)
+    };
+    timeout(TimeoutKind::Connect, config.connect_timeout, connect).await?
+}
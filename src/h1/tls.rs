@@ -1,3 +1,5 @@
+use std::error::Error as StdError;
+use std::fmt::{self, Debug};
 use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::Arc;
@@ -10,44 +12,71 @@ use futures::task::{Context, Poll};
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "rustls")] {
-        use async_tls::client::TlsStream;
+        use futures_rustls::client::TlsStream;
     } else if #[cfg(feature = "native-tls")] {
         use async_native_tls::TlsStream;
     }
 }
 
-use crate::{Config, Error};
+use super::pool::{Conns, Pooled};
+use crate::proxy::tunnel::Tunnel;
+use crate::timeout::timeout;
+use crate::{ClientError, ClientErrorKind, Config, Error, TimeoutKind};
 
 #[derive(Clone)]
 #[cfg_attr(not(feature = "rustls"), derive(std::fmt::Debug))]
 pub(crate) struct TlsConnection {
     host: String,
     addr: SocketAddr,
+    tunnel: Option<Tunnel>,
     config: Arc<Config>,
+    connector: Connector,
+    conns: Arc<Conns<TlsStream<TcpStream>>>,
 }
 
 impl TlsConnection {
-    pub(crate) fn new(host: String, addr: SocketAddr, config: Arc<Config>) -> Self {
-        Self { host, addr, config }
+    pub(crate) fn new(
+        host: String,
+        addr: SocketAddr,
+        tunnel: Option<Tunnel>,
+        config: Arc<Config>,
+        connector: Connector,
+        conns: Arc<Conns<TlsStream<TcpStream>>>,
+    ) -> Self {
+        Self {
+            host,
+            addr,
+            tunnel,
+            config,
+            connector,
+            conns,
+        }
     }
 }
 
 pub(crate) struct TlsConnWrapper {
-    conn: Object<TlsStream<TcpStream>, Error>,
+    conn: Object<Pooled<TlsStream<TcpStream>>, Error>,
 }
 impl TlsConnWrapper {
-    pub(crate) fn new(conn: Object<TlsStream<TcpStream>, Error>) -> Self {
+    pub(crate) fn new(mut conn: Object<Pooled<TlsStream<TcpStream>>, Error>) -> Self {
+        conn.acquire();
         Self { conn }
     }
 }
 
+impl Drop for TlsConnWrapper {
+    fn drop(&mut self) {
+        self.conn.release();
+    }
+}
+
 impl AsyncRead for TlsConnWrapper {
     fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<Result<usize, std::io::Error>> {
-        Pin::new(&mut *self.conn).poll_read(cx, buf)
+        Pin::new(&mut **self.conn).poll_read(cx, buf)
     }
 }
 
@@ -57,73 +86,544 @@ impl AsyncWrite for TlsConnWrapper {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
-        Pin::new(&mut *self.conn).poll_write(cx, buf)
+        Pin::new(&mut **self.conn).poll_write(cx, buf)
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut *self.conn).poll_flush(cx)
+        Pin::new(&mut **self.conn).poll_flush(cx)
     }
 
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut *self.conn).poll_close(cx)
+        Pin::new(&mut **self.conn).poll_close(cx)
     }
 }
 
 #[async_trait]
-impl Manager<TlsStream<TcpStream>, Error> for TlsConnection {
-    async fn create(&self) -> Result<TlsStream<TcpStream>, Error> {
-        let raw_stream = async_std::net::TcpStream::connect(self.addr).await?;
-
-        raw_stream.set_nodelay(self.config.tcp_no_delay)?;
-
-        let tls_stream = add_tls(&self.host, raw_stream, &self.config).await?;
-        Ok(tls_stream)
+impl Manager<Pooled<TlsStream<TcpStream>>, Error> for TlsConnection {
+    async fn create(&self) -> Result<Pooled<TlsStream<TcpStream>>, Error> {
+        let tunnel = self.tunnel.as_ref();
+        let conn = connect(&self.host, self.addr, tunnel, &self.config, &self.connector).await?;
+        Ok(Pooled::new(conn, &self.conns))
     }
 
-    async fn recycle(&self, conn: &mut TlsStream<TcpStream>) -> RecycleResult<Error> {
-        let mut buf = [0; 4];
-        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
-
-        conn.get_ref()
+    async fn recycle(&self, conn: &mut Pooled<TlsStream<TcpStream>>) -> RecycleResult<Error> {
+        conn.recycle(&self.config)?;
+        tcp_stream(conn)
             .set_nodelay(self.config.tcp_no_delay)
             .map_err(Error::from)?;
+        Ok(())
+    }
+}
 
-        match Pin::new(conn).poll_read(&mut cx, &mut buf) {
-            Poll::Ready(Err(error)) => Err(error),
-            Poll::Ready(Ok(bytes)) if bytes == 0 => Err(std::io::Error::new(
-                std::io::ErrorKind::UnexpectedEof,
-                "connection appeared to be closed (EoF)",
-            )),
-            _ => Ok(()),
-        }
-        .map_err(Error::from)?;
+/// What the TLS connections of a client are opened with, built from its `Config` once, or why
+/// building it failed, which `https` requests report.
+#[derive(Clone)]
+pub(crate) struct Connector(Result<TlsConfig, Arc<dyn StdError + Send + Sync>>);
 
-        Ok(())
+impl Connector {
+    /// The connector of `config`: its `tls_config` when set, or one built from its `tls`.
+    pub(crate) fn new(config: &Config) -> Self {
+        Self(tls_config(config).map_err(Arc::from))
+    }
+
+    fn get(&self) -> Result<&TlsConfig, ClientError> {
+        self.0
+            .as_ref()
+            .map_err(|e| ClientError::new(ClientErrorKind::Tls, e.clone()))
     }
 }
 
+impl Debug for Connector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Ok(_) => f.write_str("Connector"),
+            Err(e) => f.debug_tuple("Connector").field(e).finish(),
+        }
+    }
+}
+
+/// Open a TLS connection with `connector` to `host` at `addr`, or through `tunnel` when `addr`
+/// is a proxy.
+pub(crate) async fn connect(
+    host: &str,
+    addr: SocketAddr,
+    tunnel: Option<&Tunnel>,
+    config: &Config,
+    connector: &Connector,
+) -> Result<TlsStream<TcpStream>, Error> {
+    let tls_config = connector.get()?;
+    let raw_stream = super::tcp::connect(addr, tunnel, config).await?;
+
+    let handshake = add_tls(host, raw_stream, tls_config, config);
+    let duration = config.tls_handshake_timeout;
+    let tls_stream = timeout(TimeoutKind::TlsHandshake, duration, handshake)
+        .await?
+        .map_err(ClientError::into_error)?;
+    Ok(tls_stream)
+}
+
 cfg_if::cfg_if! {
     if #[cfg(feature = "rustls")] {
-        #[allow(unused_variables)]
-        pub(crate) async fn add_tls(host: &str, stream: TcpStream, config: &Config) -> Result<TlsStream<TcpStream>, std::io::Error> {
-            let connector = if let Some(tls_config) = config.tls_config.as_ref().cloned() {
-                tls_config.into()
-            } else {
-                async_tls::TlsConnector::default()
+        use std::cell::RefCell;
+        use std::future::Future;
+        use std::io;
+
+        use futures_rustls::TlsConnector;
+        use rustls_crate::internal::msgs::handshake::DigitallySignedStruct;
+        use rustls_crate::internal::pemfile;
+        use rustls_crate::{
+            ClientConfig, ClientSessionMemoryCache, HandshakeSignatureValid, ProtocolVersion, RootCertStore,
+            ServerCertVerified, ServerCertVerifier, Session, SignatureScheme, TLSError,
+        };
+        use webpki::DNSNameRef;
+
+        use crate::tls::{Encoding, IdentityKind};
+        use crate::{Identity, KeyPins, TlsInfo, TlsSettings, TlsVersion};
+
+        type TlsConfig = Arc<ClientConfig>;
+
+        /// The configuration of `config`, with its key pins checked by the certificate verifier.
+        fn tls_config(config: &Config) -> Result<TlsConfig, Box<dyn StdError + Send + Sync>> {
+            let tls_config = match &config.tls_config {
+                Some(tls_config) => tls_config.clone(),
+                None => Arc::new(rustls_config(&config.tls)?),
             };
+            match &config.key_pins {
+                Some(key_pins) => {
+                    let verifier = PinningVerifier {
+                        inner: tls_config.clone(),
+                        key_pins: key_pins.clone(),
+                    };
+                    let mut tls_config = ClientConfig::clone(&tls_config);
+                    tls_config.dangerous().set_certificate_verifier(Arc::new(verifier));
+                    // Resumed sessions are not verified again, so none verified without the
+                    // pins may be resumed.
+                    tls_config.set_persistence(ClientSessionMemoryCache::new(32));
+                    Ok(Arc::new(tls_config))
+                }
+                None => Ok(tls_config),
+            }
+        }
+
+        /// Complete a TLS handshake with `host` over `stream`. Key pins are checked by the
+        /// verifier of `tls_config`.
+        async fn add_tls(
+            host: &str,
+            stream: TcpStream,
+            tls_config: &TlsConfig,
+            _config: &Config,
+        ) -> Result<TlsStream<TcpStream>, ClientError> {
+            let domain = DNSNameRef::try_from_ascii_str(host)
+                .map_err(|e| ClientError::new(ClientErrorKind::Tls, e))?;
+            let connector = TlsConnector::from(tls_config.clone());
+            let handshake = Verifying {
+                connect: connector.connect(domain, stream),
+                verified: Some(Verified::default()),
+            };
+            match handshake.await {
+                (Ok(stream), _) => Ok(stream),
+                (Err(_), Verified { pin_mismatch: Some(e) }) => Err(e),
+                (Err(e), _) => Err(ClientError::new(ClientErrorKind::Tls, e)),
+            }
+        }
+
+        /// The TCP connection under `stream`.
+        pub(crate) fn tcp_stream(stream: &TlsStream<TcpStream>) -> &TcpStream {
+            stream.get_ref().0
+        }
+
+        /// What was negotiated in the handshake of `stream`.
+        pub(crate) fn tls_info(stream: &TlsStream<TcpStream>) -> TlsInfo {
+            let session = stream.get_ref().1;
+            let version = session.get_protocol_version().and_then(|version| match version {
+                ProtocolVersion::TLSv1_0 => Some(TlsVersion::Tls1_0),
+                ProtocolVersion::TLSv1_1 => Some(TlsVersion::Tls1_1),
+                ProtocolVersion::TLSv1_2 => Some(TlsVersion::Tls1_2),
+                ProtocolVersion::TLSv1_3 => Some(TlsVersion::Tls1_3),
+                _ => None,
+            });
+            let certs = session.get_peer_certificates().unwrap_or_default();
+            TlsInfo {
+                version,
+                cipher: session
+                    .get_negotiated_ciphersuite()
+                    .map(|suite| format!("{:?}", suite.suite)),
+                alpn_protocol: session.get_alpn_protocol().map(|protocol| protocol.to_vec()),
+                peer_certificates: certs.into_iter().map(|cert| cert.0).collect(),
+            }
+        }
+
+        /// What `PinningVerifier` found in a handshake.
+        #[derive(Default)]
+        struct Verified {
+            /// Why the certificate of the server failed its key pins, if it did.
+            pin_mismatch: Option<ClientError>,
+        }
+
+        thread_local! {
+            /// Where `PinningVerifier` records what it finds, while `Verifying` polls a
+            /// handshake on this thread.
+            static VERIFIED: RefCell<Option<Verified>> = const { RefCell::new(None) };
+        }
+
+        /// Polls the handshake `connect`, and gives back what `PinningVerifier` found in it.
+        ///
+        /// `rustls` verifies certificates while the handshake is polled, and one verifier is
+        /// shared by every connection of a client, so it records what it finds on the thread.
+        struct Verifying<F> {
+            connect: F,
+            verified: Option<Verified>,
+        }
+
+        impl<F: Future + Unpin> Future for Verifying<F> {
+            type Output = (F::Output, Verified);
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+                let this = &mut *self;
+                let outer = VERIFIED.with(|slot| slot.replace(this.verified.take()));
+                let poll = Pin::new(&mut this.connect).poll(cx);
+                this.verified = VERIFIED.with(|slot| slot.replace(outer));
+                poll.map(|output| (output, this.verified.take().unwrap_or_default()))
+            }
+        }
+
+        /// Verifies certificates with the verifier of `inner`, then checks that they have one
+        /// of the keys pinned for their host.
+        struct PinningVerifier {
+            inner: Arc<ClientConfig>,
+            key_pins: KeyPins,
+        }
+
+        impl ServerCertVerifier for PinningVerifier {
+            fn verify_server_cert(
+                &self,
+                roots: &RootCertStore,
+                presented_certs: &[rustls_crate::Certificate],
+                dns_name: DNSNameRef<'_>,
+                ocsp_response: &[u8],
+            ) -> Result<ServerCertVerified, TLSError> {
+                let verified = self.inner.get_verifier().verify_server_cert(
+                    roots,
+                    presented_certs,
+                    dns_name,
+                    ocsp_response,
+                )?;
+                let host: &str = dns_name.into();
+                let cert = presented_certs.first().map(|cert| cert.as_ref());
+                if let Err(e) = self.key_pins.check(host, cert) {
+                    let msg = e.to_string();
+                    VERIFIED.with(|slot| {
+                        if let Some(verified) = slot.borrow_mut().as_mut() {
+                            verified.pin_mismatch = Some(e);
+                        }
+                    });
+                    return Err(TLSError::General(msg));
+                }
+                Ok(verified)
+            }
+
+            fn verify_tls12_signature(
+                &self,
+                message: &[u8],
+                cert: &rustls_crate::Certificate,
+                dss: &DigitallySignedStruct,
+            ) -> Result<HandshakeSignatureValid, TLSError> {
+                self.inner.get_verifier().verify_tls12_signature(message, cert, dss)
+            }
+
+            fn verify_tls13_signature(
+                &self,
+                message: &[u8],
+                cert: &rustls_crate::Certificate,
+                dss: &DigitallySignedStruct,
+            ) -> Result<HandshakeSignatureValid, TLSError> {
+                self.inner.get_verifier().verify_tls13_signature(message, cert, dss)
+            }
 
-            connector.connect(host, stream).await
+            fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+                self.inner.get_verifier().supported_verify_schemes()
+            }
+        }
+
+        /// A `rustls` configuration with `settings`.
+        fn rustls_config(settings: &TlsSettings) -> io::Result<ClientConfig> {
+            let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+            let pem_certs = |pem: &[u8]| match pemfile::certs(&mut &pem[..]) {
+                Ok(certs) if !certs.is_empty() => Ok(certs),
+                _ => Err(invalid("no certificates found in PEM data".to_string())),
+            };
+
+            let mut tls_config = ClientConfig::new();
+            if settings.built_in_roots {
+                tls_config
+                    .root_store
+                    .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+            }
+            for certificate in &settings.root_certificates {
+                let certs = match certificate.encoding {
+                    Encoding::Pem => pem_certs(&certificate.data)?,
+                    Encoding::Der => vec![rustls_crate::Certificate(certificate.data.clone())],
+                };
+                for cert in &certs {
+                    tls_config
+                        .root_store
+                        .add(cert)
+                        .map_err(|e| invalid(format!("invalid root certificate: {:?}", e)))?;
+                }
+            }
+
+            match &settings.identity {
+                Some(Identity {
+                    kind: IdentityKind::Pem { certs, key, .. },
+                }) => {
+                    let certs = pem_certs(certs)?;
+                    let mut keys = pemfile::pkcs8_private_keys(&mut &key[..]).unwrap_or_default();
+                    if keys.is_empty() {
+                        keys = pemfile::rsa_private_keys(&mut &key[..]).unwrap_or_default();
+                    }
+                    let key = keys
+                        .into_iter()
+                        .next()
+                        .ok_or_else(|| invalid("no private key found in PEM data".to_string()))?;
+                    tls_config
+                        .set_single_client_cert(certs, key)
+                        .map_err(|e| invalid(e.to_string()))?;
+                }
+                Some(Identity {
+                    kind: IdentityKind::Pkcs12 { .. },
+                }) => return Err(invalid("rustls does not read PKCS #12 identities".to_string())),
+                None => {}
+            }
+
+            let min = settings.min_version.unwrap_or(TlsVersion::Tls1_2);
+            let max = settings.max_version.unwrap_or(TlsVersion::Tls1_3);
+            tls_config.versions = [
+                (TlsVersion::Tls1_3, ProtocolVersion::TLSv1_3),
+                (TlsVersion::Tls1_2, ProtocolVersion::TLSv1_2),
+            ]
+            .iter()
+            .filter(|(version, _)| min <= *version && *version <= max)
+            .map(|(_, protocol)| *protocol)
+            .collect();
+            if tls_config.versions.is_empty() {
+                return Err(invalid("rustls only supports TLS 1.2 and 1.3".to_string()));
+            }
+            Ok(tls_config)
         }
     } else if #[cfg(feature = "native-tls")] {
-        #[allow(unused_variables)]
-        pub(crate) async fn add_tls(
+        use crate::TlsInfo;
+
+        type TlsConfig = Arc<async_native_tls::TlsConnector>;
+
+        fn tls_config(config: &Config) -> Result<TlsConfig, Box<dyn StdError + Send + Sync>> {
+            match &config.tls_config {
+                Some(connector) => Ok(connector.clone()),
+                None => Ok(Arc::new(crate::tls::native_tls_builder(&config.tls)?.into())),
+            }
+        }
+
+        /// Complete a TLS handshake with `host` over `stream`, and check its key pins.
+        async fn add_tls(
             host: &str,
             stream: TcpStream,
+            connector: &TlsConfig,
             config: &Config,
-        ) -> Result<TlsStream<TcpStream>, async_native_tls::Error> {
-            let connector = config.tls_config.as_ref().cloned().unwrap_or_default();
+        ) -> Result<TlsStream<TcpStream>, ClientError> {
+            let tls_error = |e| ClientError::new(ClientErrorKind::Tls, e);
+            let stream = connector.connect(host, stream).await.map_err(tls_error)?;
+
+            if let Some(key_pins) = &config.key_pins {
+                let cert = stream
+                    .peer_certificate()
+                    .and_then(|cert| cert.map(|cert| cert.to_der()).transpose())
+                    .map_err(tls_error)?;
+                key_pins.check(host, cert.as_deref())?;
+            }
+            Ok(stream)
+        }
+
+        /// The TCP connection under `stream`.
+        pub(crate) fn tcp_stream(stream: &TlsStream<TcpStream>) -> &TcpStream {
+            stream.get_ref()
+        }
+
+        /// What was negotiated in the handshake of `stream`, which is only the certificate of the
+        /// server with `native-tls`.
+        pub(crate) fn tls_info(stream: &TlsStream<TcpStream>) -> TlsInfo {
+            let cert = stream.peer_certificate().ok().flatten();
+            TlsInfo {
+                peer_certificates: cert.and_then(|cert| cert.to_der().ok()).into_iter().collect(),
+                ..Default::default()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::h1::H1Client;
+    use crate::test_servers::{spawn_tls_origin, CA, OTHER_PIN, SERVER_PIN};
+    use crate::{
+        Certificate, ClientError, HttpClient, Identity, KeyPins, Request, StaticResolver, TlsInfo,
+        TlsSettings, TlsVersion,
+    };
+    use http_types::{Method, Url};
+    use std::convert::TryFrom;
+
+    const CLIENT_CERT: &[u8] = include_bytes!("../../tests/fixtures/client.pem");
+    const CLIENT_KEY: &[u8] = include_bytes!("../../tests/fixtures/client.key");
+
+    /// A config which resolves `a.test` and `b.test` to `addr`, and trusts the test CA.
+    fn test_config(addr: SocketAddr, tls: TlsSettings) -> Config {
+        let resolver = StaticResolver::new()
+            .with_host("a.test", vec![addr.ip()])
+            .with_host("b.test", vec![addr.ip()]);
+        let tls = tls.add_root_certificate(Certificate::from_pem(CA));
+        Config::new()
+            .set_resolver(Some(Arc::new(resolver)))
+            .set_tls(tls)
+    }
+
+    #[async_std::test]
+    async fn pools_connections_per_host() -> http_types::Result<()> {
+        let (addr, names) = spawn_tls_origin(false).await;
+        let client = H1Client::try_from(test_config(addr, TlsSettings::new()))?;
+
+        for host in ["a.test", "b.test", "a.test", "b.test"] {
+            let url = Url::parse(&format!("https://{}:{}/", host, addr.port()))?;
+            let mut res = client.send(Request::new(Method::Get, url)).await?;
+            assert_eq!(res.body_string().await?, "ok");
+        }
+        assert_eq!(*names.lock().unwrap(), ["a.test", "b.test"]);
 
-            connector.connect(host, stream).await
+        let stats = client.pool_stats();
+        let origins: Vec<_> = stats.iter().map(|stats| stats.origin.clone()).collect();
+        let port = addr.port();
+        assert_eq!(
+            origins,
+            [
+                format!("https://a.test:{}", port),
+                format!("https://b.test:{}", port)
+            ]
+        );
+        assert!(stats
+            .iter()
+            .all(|stats| stats.created == 1 && stats.recycled == 1));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn presents_client_certificates() -> http_types::Result<()> {
+        let (addr, _) = spawn_tls_origin(true).await;
+        let url = Url::parse(&format!("https://a.test:{}/", addr.port()))?;
+
+        let client = H1Client::try_from(test_config(addr, TlsSettings::new()))?;
+        assert!(client
+            .send(Request::new(Method::Get, url.clone()))
+            .await
+            .is_err());
+
+        let identity = Identity::from_pem(CLIENT_CERT, CLIENT_KEY);
+        let tls = TlsSettings::new().set_identity(Some(identity));
+        let client = H1Client::try_from(test_config(addr, tls))?;
+        let mut res = client.send(Request::new(Method::Get, url)).await?;
+        assert_eq!(res.body_string().await?, "ok");
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn checks_key_pins() -> http_types::Result<()> {
+        let (addr, _) = spawn_tls_origin(false).await;
+        let url = |host| Url::parse(&format!("https://{}:{}/", host, addr.port()));
+
+        let key_pins = KeyPins::new().with_pins("a.test", [SERVER_PIN])?;
+        let config = test_config(addr, TlsSettings::new()).set_key_pins(Some(key_pins));
+        let client = H1Client::try_from(config)?;
+        let mut res = client
+            .send(Request::new(Method::Get, url("a.test")?))
+            .await?;
+        assert_eq!(res.body_string().await?, "ok");
+
+        let key_pins = KeyPins::new().with_pins("a.test", [OTHER_PIN])?;
+        let config = test_config(addr, TlsSettings::new()).set_key_pins(Some(key_pins));
+        let client = H1Client::try_from(config)?;
+        let err = client
+            .send(Request::new(Method::Get, url("a.test")?))
+            .await
+            .unwrap_err();
+        let kind = ClientError::of(&err).unwrap().kind();
+        assert_eq!(kind, ClientErrorKind::PinnedKeyMismatch);
+
+        let mut res = client
+            .send(Request::new(Method::Get, url("b.test")?))
+            .await?;
+        assert_eq!(res.body_string().await?, "ok");
+        Ok(())
+    }
+
+    #[cfg(feature = "rustls")]
+    #[async_std::test]
+    async fn pins_keys_over_custom_verifiers() -> http_types::Result<()> {
+        use rustls_crate::{
+            ClientConfig, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError,
+        };
+
+        /// Accepts any certificate, which the default verifier would not without roots.
+        struct AcceptAny;
+
+        impl ServerCertVerifier for AcceptAny {
+            fn verify_server_cert(
+                &self,
+                _roots: &RootCertStore,
+                _presented_certs: &[rustls_crate::Certificate],
+                _dns_name: webpki::DNSNameRef<'_>,
+                _ocsp_response: &[u8],
+            ) -> Result<ServerCertVerified, TLSError> {
+                Ok(ServerCertVerified::assertion())
+            }
+        }
+
+        let (addr, _) = spawn_tls_origin(false).await;
+        let url = Url::parse(&format!("https://a.test:{}/", addr.port()))?;
+        let mut tls_config = ClientConfig::new();
+        tls_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(AcceptAny));
+        let config =
+            test_config(addr, TlsSettings::new()).set_tls_config(Some(Arc::new(tls_config)));
+
+        let key_pins = KeyPins::new().with_pins("a.test", [SERVER_PIN])?;
+        let client = H1Client::try_from(config.clone().set_key_pins(Some(key_pins)))?;
+        let mut res = client.send(Request::new(Method::Get, url.clone())).await?;
+        assert_eq!(res.body_string().await?, "ok");
+
+        let key_pins = KeyPins::new().with_pins("a.test", [OTHER_PIN])?;
+        let client = H1Client::try_from(config.set_key_pins(Some(key_pins)))?;
+        let err = client
+            .send(Request::new(Method::Get, url))
+            .await
+            .unwrap_err();
+        let kind = ClientError::of(&err).unwrap().kind();
+        assert_eq!(kind, ClientErrorKind::PinnedKeyMismatch);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn reports_tls_info() -> http_types::Result<()> {
+        let (addr, _) = spawn_tls_origin(false).await;
+        let client = H1Client::try_from(test_config(addr, TlsSettings::new()))?;
+        let url = Url::parse(&format!("https://a.test:{}/", addr.port()))?;
+
+        for _ in 0..2 {
+            let res = client.send(Request::new(Method::Get, url.clone())).await?;
+            let info = res.ext().get::<TlsInfo>().unwrap();
+            assert_eq!(info.peer_certificates().len(), 1);
+            if cfg!(feature = "rustls") {
+                assert_eq!(info.version(), Some(TlsVersion::Tls1_3));
+                assert!(info.cipher().unwrap().starts_with("TLS13_"));
+            }
         }
+        Ok(())
     }
 }
@@ -0,0 +1,252 @@
+//! Sending requests over Unix domain sockets.
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use async_std::os::unix::net::UnixStream;
+use async_trait::async_trait;
+use deadpool::managed::{Manager, Object, RecycleResult};
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::task::{Context, Poll};
+use http_types::url::Url;
+use percent_encoding::percent_decode_str;
+
+use super::pool::{Conns, Pooled};
+use crate::timeout::timeout;
+use crate::{ClientError, ClientErrorKind, Config, Error, Request, TimeoutKind};
+
+/// The Unix domain socket a request is sent over, instead of connecting to the host in its URL.
+///
+/// Requests can also be sent over a Unix domain socket with a `unix` or `http+unix` URL, whose
+/// host is the percent-encoded path of the socket, such as
+/// `http+unix://%2Fvar%2Frun%2Fdocker.sock/containers/json`. Those are sent to `localhost`.
+///
+/// Proxies are never used for requests over Unix domain sockets.
+///
+/// # Examples
+///
+/// ```no_run
+/// use http_client::h1::{H1Client, UnixSocket};
+/// use http_client::HttpClient;
+/// use http_types::{Method, Request};
+///
+/// # #[async_std::main]
+/// # async fn main() -> Result<(), http_types::Error> {
+/// let mut req = Request::new(Method::Get, "http://localhost/containers/json");
+/// req.ext_mut().insert(UnixSocket::new("/var/run/docker.sock"));
+/// let res = H1Client::new().send(req).await?;
+/// # Ok(()) }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UnixSocket {
+    path: PathBuf,
+}
+
+impl UnixSocket {
+    /// Create a new instance from the path of the socket.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The path of the socket.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// The path of the socket `req` is sent over, if any. Requests to `unix` URLs are changed to be
+/// sent to `localhost`.
+pub(crate) fn socket_path(req: &mut Request) -> Result<Option<PathBuf>, Error> {
+    if let Some(socket) = req.ext().get::<UnixSocket>() {
+        return Ok(Some(socket.path.clone()));
+    }
+    if !matches!(req.url().scheme(), "unix" | "http+unix") {
+        return Ok(None);
+    }
+
+    let host = req
+        .url()
+        .host_str()
+        .ok_or_else(|| super::invalid_url("missing socket path"))?;
+    let path: Vec<u8> = percent_decode_str(host).collect();
+    let path = PathBuf::from(OsStr::from_bytes(&path));
+
+    // The URL has no host, so is only used for the request line and the `Host` header.
+    let mut url = Url::parse("http://localhost/").unwrap();
+    url.set_path(req.url().path());
+    url.set_query(req.url().query());
+    *req.url_mut() = url;
+    Ok(Some(path))
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct UnixConnection {
+    path: PathBuf,
+    config: Arc<Config>,
+    conns: Arc<Conns<UnixStream>>,
+}
+
+impl UnixConnection {
+    pub(crate) fn new(path: PathBuf, config: Arc<Config>, conns: Arc<Conns<UnixStream>>) -> Self {
+        Self {
+            path,
+            config,
+            conns,
+        }
+    }
+}
+
+pub(crate) struct UnixConnWrapper {
+    conn: Object<Pooled<UnixStream>, std::io::Error>,
+}
+impl UnixConnWrapper {
+    pub(crate) fn new(mut conn: Object<Pooled<UnixStream>, std::io::Error>) -> Self {
+        conn.acquire();
+        Self { conn }
+    }
+}
+
+impl Drop for UnixConnWrapper {
+    fn drop(&mut self) {
+        self.conn.release();
+    }
+}
+
+impl AsyncRead for UnixConnWrapper {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        Pin::new(&mut **self.conn).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for UnixConnWrapper {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut **self.conn).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut **self.conn).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut **self.conn).poll_close(cx)
+    }
+}
+
+#[async_trait]
+impl Manager<Pooled<UnixStream>, std::io::Error> for UnixConnection {
+    async fn create(&self) -> Result<Pooled<UnixStream>, std::io::Error> {
+        let conn = connect(&self.path, &self.config).await?;
+        Ok(Pooled::new(conn, &self.conns))
+    }
+
+    async fn recycle(&self, conn: &mut Pooled<UnixStream>) -> RecycleResult<std::io::Error> {
+        conn.recycle(&self.config)
+    }
+}
+
+/// Connect to the socket at `path`.
+pub(crate) async fn connect(path: &Path, config: &Config) -> Result<UnixStream, std::io::Error> {
+    let connect = async {
+        UnixStream::connect(path)
+            .await
+            .map_err(|e| std::io::Error::from(ClientError::from_io(e, ClientErrorKind::Connect)))
+    };
+    timeout(TimeoutKind::Connect, config.connect_timeout, connect).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::h1::H1Client;
+    use crate::test_servers::read_head;
+    use crate::HttpClient;
+    use async_std::os::unix::net::UnixListener;
+    use async_std::prelude::*;
+    use async_std::task;
+    use http_types::Method;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A server which responds to each request with its request line and `Host` header, and
+    /// counts the connections it accepts.
+    async fn spawn_unix_origin(name: &str) -> (PathBuf, Arc<AtomicUsize>) {
+        let path = std::env::temp_dir().join(format!("{}-{}.sock", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).await.unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        task::spawn(async move {
+            while let Some(Ok(mut stream)) = listener.incoming().next().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                task::spawn(async move {
+                    loop {
+                        let head = read_head(&mut stream).await;
+                        if head.is_empty() {
+                            break;
+                        }
+                        let mut lines = head.lines();
+                        let line = lines.next().unwrap();
+                        let host = lines.find(|line| line.starts_with("host: ")).unwrap();
+                        let body = format!("{}, {}", line, host);
+                        let res = format!(
+                            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
+                            body.len(),
+                            body
+                        );
+                        stream.write_all(res.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        (path, connections)
+    }
+
+    #[async_std::test]
+    async fn sends_requests_over_unix_sockets() -> http_types::Result<()> {
+        let (path, connections) = spawn_unix_origin("http-client-unix").await;
+        let client = H1Client::new();
+
+        let encoded = path.to_str().unwrap().replace('/', "%2F");
+        let url = format!("http+unix://{}/hello?x=1", encoded);
+        let mut res = client.send(Request::new(Method::Get, url.as_str())).await?;
+        assert_eq!(
+            res.body_string().await?,
+            "GET /hello?x=1 HTTP/1.1, host: localhost"
+        );
+
+        let mut req = Request::new(Method::Get, "http://docker.example/info");
+        req.ext_mut().insert(UnixSocket::new(&path));
+        let mut res = client.send(req).await?;
+        assert_eq!(
+            res.body_string().await?,
+            "GET /info HTTP/1.1, host: docker.example"
+        );
+
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn reports_missing_sockets() -> http_types::Result<()> {
+        let mut req = Request::new(Method::Get, "http://localhost/");
+        req.ext_mut()
+            .insert(UnixSocket::new("/nonexistent/http-client.sock"));
+        let err = H1Client::new().send(req).await.unwrap_err();
+        assert_eq!(
+            ClientError::of(&err).unwrap().kind(),
+            ClientErrorKind::Connect
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,349 @@
+//! A hyper connector which sends requests through the proxies in `Config`, within its timeouts.
+
+use std::error::Error as StdError;
+use std::future::Future;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use async_native_tls::{TlsConnector, TlsStream};
+use futures_util::future::BoxFuture;
+use http_types::url::Url;
+use hyper::client::connect::dns::Name;
+use hyper::client::connect::{Connected, Connection};
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper::Uri;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+use crate::proxy::tunnel::Tunnel;
+use crate::resolve;
+use crate::timeout::timeout;
+use crate::{ClientError, ClientErrorKind, Config, Resolve, TimeoutKind, TlsInfo};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Connects to the target of a request directly, or through the proxy `Config` selects for it.
+///
+/// TLS is added on top for `https` requests, after any tunnel through the proxy is open.
+#[derive(Clone)]
+pub(crate) struct ProxyConnector {
+    http: HttpConnector<Resolver>,
+    /// The connector built from `Config::tls`, or why building it failed, which `https`
+    /// requests report.
+    tls: Result<Arc<TlsConnector>, Arc<native_tls_crate::Error>>,
+    config: Arc<Config>,
+}
+
+impl ProxyConnector {
+    pub(crate) fn new(config: Arc<Config>) -> Self {
+        let mut http = HttpConnector::new_with_resolver(Resolver(resolve::resolver(&config)));
+        http.enforce_http(false);
+        http.set_nodelay(config.tcp_no_delay);
+        http.set_happy_eyeballs_timeout(config.happy_eyeballs_delay);
+        let tls = crate::tls::native_tls_builder(&config.tls)
+            .map(|builder| Arc::new(TlsConnector::from(builder)))
+            .map_err(Arc::new);
+        Self { http, tls, config }
+    }
+}
+
+impl std::fmt::Debug for ProxyConnector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProxyConnector")
+            .field("http", &self.http)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl Service<Uri> for ProxyConnector {
+    type Response = MaybeTlsStream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        self.http
+            .poll_ready(cx)
+            .map_err(|e| connect_error(e).into())
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let http = self.http.clone();
+        let tls = self.tls.clone();
+        let config = self.config.clone();
+
+        // Errors are always `ClientError`s, which `HyperClient` hands on as they are.
+        Box::pin(async move {
+            let url = Url::parse(&dst.to_string())
+                .map_err(|e| ClientError::new(ClientErrorKind::InvalidUrl, e))?;
+            let connect = connect(http, dst, &url, &config);
+            let stream = timeout(TimeoutKind::Connect, config.connect_timeout, connect)
+                .await
+                .map_err(|e| ClientError::from_io(e, ClientErrorKind::Connect))??;
+            if url.scheme() != "https" {
+                return Ok(MaybeTlsStream::Plain(stream));
+            }
+
+            let tls = tls.map_err(|e| ClientError::new(ClientErrorKind::Tls, e))?;
+            let host = url.host_str().unwrap_or_default();
+            let host = host.trim_matches(|c| c == '[' || c == ']');
+            let tls_error = |e| ClientError::new(ClientErrorKind::Tls, e);
+            let handshake = tls.connect(host, Compat(stream));
+            let duration = config.tls_handshake_timeout;
+            let stream = timeout(TimeoutKind::TlsHandshake, duration, handshake)
+                .await
+                .map_err(|e| ClientError::from_io(e, ClientErrorKind::Tls))?
+                .map_err(tls_error)?;
+
+            if let Some(key_pins) = &config.key_pins {
+                let cert = stream
+                    .peer_certificate()
+                    .and_then(|cert| cert.map(|cert| cert.to_der()).transpose())
+                    .map_err(tls_error)?;
+                key_pins.check(host, cert.as_deref())?;
+            }
+            Ok(MaybeTlsStream::Tls(stream))
+        })
+    }
+}
+
+/// Open a connection to `dst`, or to the proxy in front of it, and on through any tunnel.
+async fn connect(
+    mut http: HttpConnector<Resolver>,
+    dst: Uri,
+    url: &Url,
+    config: &Config,
+) -> Result<ProxyStream, ClientError> {
+    let proxy = match config.proxy_for(url) {
+        Some(proxy) => proxy.clone(),
+        None => {
+            return Ok(ProxyStream {
+                inner: http.call(dst).await.map_err(connect_error)?,
+                absolute_form: false,
+            })
+        }
+    };
+
+    // SOCKS proxies have no default port in URLs, but conventionally listen on 1080.
+    let proxy_uri = format!(
+        "http://{}:{}",
+        proxy.url().host_str().unwrap_or_default(),
+        proxy.url().port_or_known_default().unwrap_or(1080)
+    );
+    let proxy_uri = proxy_uri
+        .parse()
+        .map_err(|e| ClientError::new(ClientErrorKind::InvalidUrl, e))?;
+    let mut stream = http.call(proxy_uri).await.map_err(connect_error)?;
+
+    // An HTTP proxy is sent plain `http` requests in absolute-form. Anything else going
+    // through a proxy is tunnelled to its target.
+    let absolute_form = url.scheme() == "http" && proxy.url().scheme() == "http";
+    if !absolute_form {
+        let tunnel = Tunnel::new(proxy, url, resolve::resolver(config))
+            .ok_or_else(|| ClientError::new(ClientErrorKind::InvalidUrl, "missing hostname"))?;
+        tunnel
+            .establish(&mut Compat(&mut stream))
+            .await
+            .map_err(|e| ClientError::from_io(e, ClientErrorKind::Proxy))?;
+    }
+
+    Ok(ProxyStream {
+        inner: stream,
+        absolute_form,
+    })
+}
+
+/// A failure to connect, which is a DNS error when `Resolver` failed.
+fn connect_error(err: impl StdError + Send + Sync + 'static) -> ClientError {
+    let mut source = err.source();
+    while let Some(cause) = source {
+        if cause.is::<ClientError>() {
+            return ClientError::new(ClientErrorKind::Dns, err);
+        }
+        source = cause.source();
+    }
+    ClientError::new(ClientErrorKind::Connect, err)
+}
+
+/// Resolves hostnames for `HttpConnector` with the resolver in `Config`, marking its failures as
+/// `ClientError`s.
+#[derive(Clone, Debug)]
+pub(crate) struct Resolver(Arc<dyn Resolve>);
+
+impl Service<Name> for Resolver {
+    type Response = std::vec::IntoIter<IpAddr>;
+    type Error = ClientError;
+    type Future = BoxFuture<'static, Result<Self::Response, ClientError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), ClientError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let resolver = self.0.clone();
+        Box::pin(async move {
+            // `HttpConnector` sets the port on the addresses itself.
+            let addrs = resolve::lookup(&*resolver, name.as_str(), 0)
+                .await
+                .map_err(|e| ClientError::from_io(e, ClientErrorKind::Dns))?;
+            let ips: Vec<_> = addrs.into_iter().map(|addr| addr.ip()).collect();
+            Ok(ips.into_iter())
+        })
+    }
+}
+
+/// A connection to the target of a request, or to the proxy in front of it.
+pub(crate) struct ProxyStream {
+    inner: TcpStream,
+    absolute_form: bool,
+}
+
+impl Connection for ProxyStream {
+    fn connected(&self) -> Connected {
+        // Tells hyper to send requests in absolute-form.
+        self.inner.connected().proxy(self.absolute_form)
+    }
+}
+
+impl AsyncRead for ProxyStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ProxyStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// A connection to the target of a request, with TLS on top for `https` requests.
+///
+/// `async-native-tls` is written against the `futures` I/O traits, so the connection under TLS
+/// is exposed through them, and TLS through the tokio ones hyper uses.
+pub(crate) enum MaybeTlsStream {
+    Plain(ProxyStream),
+    Tls(TlsStream<Compat<ProxyStream>>),
+}
+
+impl Connection for MaybeTlsStream {
+    fn connected(&self) -> Connected {
+        match self {
+            MaybeTlsStream::Plain(stream) => stream.connected(),
+            MaybeTlsStream::Tls(stream) => stream.get_ref().0.connected().extra(tls_info(stream)),
+        }
+    }
+}
+
+impl AsyncRead for MaybeTlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            MaybeTlsStream::Tls(stream) => {
+                futures_util::io::AsyncRead::poll_read(Pin::new(stream), cx, buf)
+            }
+        }
+    }
+}
+
+impl AsyncWrite for MaybeTlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            MaybeTlsStream::Tls(stream) => {
+                futures_util::io::AsyncWrite::poll_write(Pin::new(stream), cx, buf)
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            MaybeTlsStream::Tls(stream) => {
+                futures_util::io::AsyncWrite::poll_flush(Pin::new(stream), cx)
+            }
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            MaybeTlsStream::Tls(stream) => {
+                futures_util::io::AsyncWrite::poll_close(Pin::new(stream), cx)
+            }
+        }
+    }
+}
+
+/// What was negotiated in the handshake of `stream`, which is only the certificate of the server
+/// with `native-tls`.
+fn tls_info(stream: &TlsStream<Compat<ProxyStream>>) -> TlsInfo {
+    let cert = stream.peer_certificate().ok().flatten();
+    TlsInfo {
+        peer_certificates: cert
+            .and_then(|cert| cert.to_der().ok())
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    }
+}
+
+/// Exposes a tokio stream through the `futures` I/O traits, which `Tunnel` and
+/// `async-native-tls` are written against.
+pub(crate) struct Compat<S>(S);
+
+impl<S: AsyncRead + Unpin> futures_util::io::AsyncRead for Compat<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> futures_util::io::AsyncWrite for Compat<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,620 @@
+//! http-client implementation for reqwest
+
+use std::convert::{Infallible, TryFrom};
+use std::error::Error as _;
+use std::fmt::Debug;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use futures_util::io::AsyncBufReadExt;
+use futures_util::stream::{self, TryStreamExt};
+use http_types::headers::{HeaderName, HeaderValue, PROXY_AUTHORIZATION};
+use hyper::body::{Bytes, HttpBody};
+use hyper::client::connect::Connect;
+
+use crate::error::{body_read_errors, classify};
+use crate::timeout;
+use crate::{ClientError, ClientErrorKind, Config, TimeoutKind, TlsInfo};
+
+use super::{async_trait, Error, HttpClient, Request, Response};
+
+mod connector;
+
+use connector::ProxyConnector;
+
+type HyperRequest = hyper::Request<hyper::Body>;
+
+// Avoid leaking Hyper generics into HttpClient by hiding it behind a dynamic trait object pointer.
+trait HyperClientObject: Debug + Send + Sync + 'static {
+    fn dyn_request(&self, req: hyper::Request<hyper::Body>) -> hyper::client::ResponseFuture;
+}
+
+impl<C: Clone + Connect + Debug + Send + Sync + 'static> HyperClientObject for hyper::Client<C> {
+    fn dyn_request(&self, req: HyperRequest) -> hyper::client::ResponseFuture {
+        self.request(req)
+    }
+}
+
+/// Hyper-based HTTP Client.
+#[derive(Debug)]
+pub struct HyperClient {
+    client: Box<dyn HyperClientObject>,
+    config: Config,
+}
+
+impl HyperClient {
+    /// Create a new client instance.
+    pub fn new() -> Self {
+        let config = Config::default();
+
+        Self {
+            client: Box::new(build_client(&config)),
+            config,
+        }
+    }
+
+    /// Create from externally initialized and configured client.
+    ///
+    /// The client's own connector is used, so proxies in `Config` have no effect.
+    pub fn from_client<C>(client: hyper::Client<C>) -> Self
+    where
+        C: Clone + Connect + Debug + Send + Sync + 'static,
+    {
+        Self {
+            client: Box::new(client),
+            config: Config::default(),
+        }
+    }
+}
+
+impl Default for HyperClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl HttpClient for HyperClient {
+    async fn send(&self, mut req: Request) -> Result<Response, Error> {
+        #[cfg(feature = "decompression")]
+        let method = req.method();
+        #[cfg(feature = "decompression")]
+        if self.config.decompress {
+            crate::decompress::prepare(&mut req);
+        }
+
+        // Plain `http` requests sent to an HTTP proxy carry its credentials. Anything else is
+        // tunnelled by the connector.
+        if let Some(proxy) = self.config.proxy_for(req.url()) {
+            if req.url().scheme() == "http" && proxy.url().scheme() == "http" {
+                if let Some(authorization) = proxy.authorization() {
+                    req.insert_header(PROXY_AUTHORIZATION, authorization);
+                }
+            }
+        }
+
+        let req = HyperHttpRequest::try_from(req).await?.into_inner();
+        let res = timeout::send(&self.config, self.request(req))
+            .await
+            .map_err(|e| classify(e, ClientErrorKind::Other))?;
+
+        #[cfg(feature = "decompression")]
+        if self.config.decompress {
+            return Ok(body_read_errors(crate::decompress::decode(method, res)));
+        }
+
+        Ok(body_read_errors(res))
+    }
+
+    /// Override the existing configuration with new configuration.
+    ///
+    /// Config options may not impact existing connections.
+    fn set_config(&mut self, config: Config) -> http_types::Result<()> {
+        self.client = Box::new(build_client(&config));
+        self.config = config;
+
+        Ok(())
+    }
+
+    /// Get the current configuration.
+    fn config(&self) -> &Config {
+        &self.config
+    }
+}
+
+impl HyperClient {
+    /// Send `req`, within the response header timeout.
+    async fn request(&self, req: hyper::Request<hyper::Body>) -> Result<Response, Error> {
+        let conn_fut = self.client.dyn_request(req);
+        let duration = self.config.response_header_timeout.or(self.config.timeout);
+        let response = timeout::timeout(TimeoutKind::ResponseHeader, duration, conn_fut)
+            .await?
+            .map_err(into_error)?;
+
+        Ok(HttpTypesResponse::try_from(response).await?.into_inner())
+    }
+}
+
+/// Convert a hyper error, handing on the `ClientError` from the connector as it is.
+fn into_error(err: hyper::Error) -> Error {
+    if matches!(err.source(), Some(cause) if cause.is::<ClientError>()) {
+        // Just checked, so neither can fail.
+        let cause = err.into_cause().unwrap();
+        return cause.downcast::<ClientError>().unwrap().into_error();
+    }
+
+    let kind = if err.is_connect() {
+        ClientErrorKind::Connect
+    } else if err.is_parse() {
+        ClientErrorKind::Protocol
+    } else {
+        ClientErrorKind::Io
+    };
+    ClientError::new(kind, err).into_error()
+}
+
+#[allow(clippy::infallible_try_from)]
+impl TryFrom<Config> for HyperClient {
+    type Error = Infallible;
+
+    fn try_from(config: Config) -> Result<Self, Self::Error> {
+        Ok(Self {
+            client: Box::new(build_client(&config)),
+            config,
+        })
+    }
+}
+
+fn build_client(config: &Config) -> hyper::Client<ProxyConnector> {
+    let connector = ProxyConnector::new(Arc::new(config.clone()));
+    let mut builder = hyper::Client::builder();
+    builder.pool_idle_timeout(config.pool_idle_timeout);
+
+    if !config.http_keep_alive {
+        builder.pool_max_idle_per_host(1);
+    }
+
+    builder.build(connector)
+}
+
+struct HyperHttpRequest(HyperRequest);
+
+impl HyperHttpRequest {
+    async fn try_from(mut value: Request) -> Result<Self, Error> {
+        // UNWRAP: This unwrap is unjustified in `http-types`, need to check if it's actually safe.
+        let uri = hyper::Uri::try_from(&format!("{}", value.url())).unwrap();
+
+        // `HyperClient` depends on the scheme being either "http" or "https"
+        match uri.scheme_str() {
+            Some("http") | Some("https") => (),
+            _ => {
+                let err = ClientError::new(ClientErrorKind::InvalidUrl, "invalid scheme");
+                return Err(err.into_error());
+            }
+        };
+
+        let mut request = hyper::Request::builder();
+
+        // UNWRAP: Default builder is safe
+        let req_headers = request.headers_mut().unwrap();
+        for (name, values) in &value {
+            // UNWRAP: http-types and http have equivalent validation rules
+            let name = hyper::header::HeaderName::from_str(name.as_str()).unwrap();
+
+            for value in values.iter() {
+                // UNWRAP: http-types and http have equivalent validation rules
+                let value =
+                    hyper::header::HeaderValue::from_bytes(value.as_str().as_bytes()).unwrap();
+                req_headers.append(&name, value);
+            }
+        }
+
+        let body = value.take_body();
+        let body = match body.len() {
+            Some(0) => hyper::Body::empty(),
+            len => {
+                if let Some(len) = len {
+                    req_headers.insert(hyper::header::CONTENT_LENGTH, len.into());
+                }
+                hyper::Body::wrap_stream(body_stream(body))
+            }
+        };
+
+        let request = request
+            .method(value.method())
+            .version(value.version().map(|v| v.into()).unwrap_or_default())
+            .uri(uri)
+            .body(body)?;
+
+        Ok(HyperHttpRequest(request))
+    }
+
+    fn into_inner(self) -> hyper::Request<hyper::Body> {
+        self.0
+    }
+}
+
+/// Read `body` a buffer at a time, for hyper to send each as it comes rather than all at once.
+fn body_stream(body: http_types::Body) -> impl stream::Stream<Item = io::Result<Bytes>> + Send {
+    stream::try_unfold(body, |mut body| async move {
+        let chunk = Bytes::copy_from_slice(body.fill_buf().await?);
+        if chunk.is_empty() {
+            return Ok(None);
+        }
+        body.consume_unpin(chunk.len());
+        Ok(Some((chunk, body)))
+    })
+}
+
+struct HttpTypesResponse(Response);
+
+impl HttpTypesResponse {
+    async fn try_from(value: hyper::Response<hyper::Body>) -> Result<Self, Error> {
+        let (parts, body) = value.into_parts();
+
+        let size_hint = body.size_hint().upper().map(|s| s as usize);
+        // `io::Error::other` needs a newer Rust than this crate supports.
+        #[allow(clippy::io_other_error)]
+        let body = body.map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()));
+        let body = http_types::Body::from_reader(body.into_async_read(), size_hint);
+
+        let mut res = Response::new(parts.status);
+        res.set_version(Some(parts.version.into()));
+
+        for (name, value) in parts.headers {
+            let value = value.as_bytes().to_owned();
+            let value = HeaderValue::from_bytes(value)?;
+
+            if let Some(name) = name {
+                let name = name.as_str();
+                let name = HeaderName::from_str(name)?;
+                res.append_header(name, value);
+            }
+        }
+
+        res.set_body(body);
+        if let Some(tls_info) = parts.extensions.get::<TlsInfo>() {
+            res.ext_mut().insert(tls_info.clone());
+        }
+        Ok(HttpTypesResponse(res))
+    }
+
+    fn into_inner(self) -> Response {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_servers::{
+        spawn_origin, spawn_proxy, spawn_silent, spawn_socks5, spawn_tls_origin, CA, OTHER_PIN,
+        SERVER_PIN,
+    };
+    use crate::{
+        Certificate, ClientError, ClientErrorKind, Config, Error, HttpClient, Identity, KeyPins,
+        Proxy, StaticResolver, TimeoutError, TimeoutKind, TlsInfo, TlsSettings,
+    };
+    use futures_util::io::BufReader;
+    use http_types::Body;
+    use http_types::{Method, Request, Url};
+    use hyper::body::HttpBody;
+    use hyper::service::{make_service_fn, service_fn};
+    use std::io;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use tokio::sync::oneshot::channel;
+
+    use super::HyperClient;
+    use std::convert::TryFrom;
+
+    async fn echo(
+        req: hyper::Request<hyper::Body>,
+    ) -> Result<hyper::Response<hyper::Body>, hyper::Error> {
+        Ok(hyper::Response::new(req.into_body()))
+    }
+
+    #[tokio::test]
+    async fn basic_functionality() {
+        let (send, recv) = channel::<()>();
+
+        let recv = async move { recv.await.unwrap_or(()) };
+
+        let addr = ([127, 0, 0, 1], portpicker::pick_unused_port().unwrap()).into();
+        let service = make_service_fn(|_| async { Ok::<_, hyper::Error>(service_fn(echo)) });
+        let server = hyper::Server::bind(&addr)
+            .serve(service)
+            .with_graceful_shutdown(recv);
+
+        let client = HyperClient::new();
+        let url = Url::parse(&format!("http://localhost:{}", addr.port())).unwrap();
+        let mut req = Request::new(Method::Get, url);
+        req.set_body("hello");
+
+        let client = async move {
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+            let mut resp = client.send(req).await?;
+            send.send(()).unwrap();
+            assert_eq!(resp.body_string().await?, "hello");
+
+            Result::<(), Error>::Ok(())
+        };
+
+        let (client_res, server_res) = tokio::join!(client, server);
+        assert!(client_res.is_ok());
+        assert!(server_res.is_ok());
+    }
+
+    /// A body of `left` generated bytes, which counts how many of them have been read.
+    struct Generated {
+        left: usize,
+        read: Arc<AtomicUsize>,
+    }
+
+    impl futures_util::io::AsyncRead for Generated {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let n = buf.len().min(self.left);
+            buf[..n].fill(b'x');
+            self.left -= n;
+            self.read.fetch_add(n, Ordering::SeqCst);
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_request_bodies() -> http_types::Result<()> {
+        const LEN: usize = 64 * 1024 * 1024;
+        let read = Arc::new(AtomicUsize::new(0));
+
+        // Answers with the length of the body, how much of it the client had read when the
+        // first chunk arrived, and how it was framed.
+        let counter = read.clone();
+        let service = make_service_fn(move |_| {
+            let counter = counter.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req: hyper::Request<hyper::Body>| {
+                    let counter = counter.clone();
+                    async move {
+                        let framing = match req.headers().get("content-length") {
+                            Some(len) => len.to_str().unwrap().to_string(),
+                            None => "chunked".to_string(),
+                        };
+                        let mut body = req.into_body();
+                        let (mut len, mut read_at_first_chunk) = (0, None);
+                        while let Some(chunk) = body.data().await {
+                            read_at_first_chunk.get_or_insert(counter.load(Ordering::SeqCst));
+                            len += chunk?.len();
+                        }
+                        let res = format!("{} {} {}", len, read_at_first_chunk.unwrap(), framing);
+                        Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::from(res)))
+                    }
+                }))
+            }
+        });
+        let addr = ([127, 0, 0, 1], portpicker::pick_unused_port().unwrap()).into();
+        tokio::spawn(hyper::Server::bind(&addr).serve(service));
+
+        let client = HyperClient::new();
+        let url = Url::parse(&format!("http://localhost:{}/", addr.port()))?;
+        for len in [Some(LEN), None] {
+            read.store(0, Ordering::SeqCst);
+            let body = Generated {
+                left: LEN,
+                read: read.clone(),
+            };
+            let mut req = Request::new(Method::Post, url.clone());
+            req.set_body(Body::from_reader(BufReader::new(body), len));
+
+            let mut res = client.send(req).await?;
+            let res = res.body_string().await?;
+            let res: Vec<_> = res.split(' ').collect();
+            assert_eq!(res[0], LEN.to_string());
+            assert!(res[1].parse::<usize>()? < LEN / 4);
+            match len {
+                Some(len) => assert_eq!(res[2], len.to_string()),
+                None => assert_eq!(res[2], "chunked"),
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sends_requests_through_proxies() -> http_types::Result<()> {
+        let origin = spawn_origin().await;
+        let url = Url::parse(&format!("http://localhost:{}/hello?x=1", origin.port()))?;
+
+        let (proxy, heads) = spawn_proxy().await;
+        let proxy = Proxy::new(format!("http://user:pass@{}", proxy))?;
+        let client = HyperClient::try_from(Config::new().set_http_proxy(Some(proxy)))?;
+        let mut res = client.send(Request::new(Method::Get, url.clone())).await?;
+        assert_eq!(res.body_string().await?, "GET /hello?x=1 HTTP/1.1");
+        let head = heads.lock().unwrap().pop().unwrap();
+        assert!(head.starts_with(&format!("GET {} HTTP/1.1\r\n", url)));
+        assert!(head.contains("proxy-authorization: Basic dXNlcjpwYXNz\r\n"));
+
+        let (proxy, hosts) = spawn_socks5().await;
+        let proxy = Proxy::new(format!("socks5h://user:pass@{}", proxy))?;
+        let client = HyperClient::try_from(Config::new().set_proxy(Some(proxy)))?;
+        let mut res = client.send(Request::new(Method::Get, url)).await?;
+        assert_eq!(res.body_string().await?, "GET /hello?x=1 HTTP/1.1");
+        assert_eq!(hosts.lock().unwrap().pop().unwrap(), "localhost");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reports_which_timeout_elapsed() -> http_types::Result<()> {
+        let addr = spawn_silent().await;
+        let timeout = Some(Duration::from_millis(100));
+        // The silent server also stands in for a proxy which never answers `CONNECT` requests.
+        let proxy = Proxy::new(format!("http://{}", addr))?;
+
+        let cases = vec![
+            (
+                Config::new().set_response_header_timeout(timeout),
+                "http",
+                TimeoutKind::ResponseHeader,
+            ),
+            (
+                Config::new().set_total_timeout(timeout),
+                "http",
+                TimeoutKind::Total,
+            ),
+            (
+                Config::new().set_tls_handshake_timeout(timeout),
+                "https",
+                TimeoutKind::TlsHandshake,
+            ),
+            (
+                Config::new()
+                    .set_connect_timeout(timeout)
+                    .set_proxy(Some(proxy)),
+                "https",
+                TimeoutKind::Connect,
+            ),
+        ];
+
+        for (config, scheme, kind) in cases {
+            let client = HyperClient::try_from(config)?;
+            let url = Url::parse(&format!("{}://localhost:{}/", scheme, addr.port()))?;
+            let err = client
+                .send(Request::new(Method::Get, url))
+                .await
+                .unwrap_err();
+            assert_eq!(TimeoutError::of(&err).unwrap().kind(), kind);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn presents_client_certificates() -> http_types::Result<()> {
+        let (addr, _) = spawn_tls_origin(true).await;
+        let url = Url::parse(&format!("https://a.test:{}/", addr.port()))?;
+        let resolver = StaticResolver::new().with_host("a.test", vec![addr.ip()]);
+        let config = Config::new().set_resolver(Some(Arc::new(resolver)));
+        let tls = TlsSettings::new().add_root_certificate(Certificate::from_pem(CA));
+
+        let p12 = include_bytes!("../../tests/fixtures/client.p12");
+        let identity = Identity::from_pkcs12(p12, "test");
+        let tls_with_identity = tls.clone().set_identity(Some(identity));
+        let client = HyperClient::try_from(config.clone().set_tls(tls_with_identity))?;
+        let mut res = client.send(Request::new(Method::Get, url.clone())).await?;
+        assert_eq!(res.body_string().await?, "ok");
+
+        let identity = Identity::from_pkcs12(b"not a PKCS #12 archive", "test");
+        let client = HyperClient::try_from(config.set_tls(tls.set_identity(Some(identity))))?;
+        let err = client
+            .send(Request::new(Method::Get, url))
+            .await
+            .unwrap_err();
+        assert_eq!(ClientError::of(&err).unwrap().kind(), ClientErrorKind::Tls);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn checks_key_pins() -> http_types::Result<()> {
+        let (addr, _) = spawn_tls_origin(false).await;
+        let resolver = StaticResolver::new()
+            .with_host("a.test", vec![addr.ip()])
+            .with_host("b.test", vec![addr.ip()]);
+        let tls = TlsSettings::new().add_root_certificate(Certificate::from_pem(CA));
+        let config = Config::new()
+            .set_resolver(Some(Arc::new(resolver)))
+            .set_tls(tls);
+        let url = |host| Url::parse(&format!("https://{}:{}/", host, addr.port()));
+
+        let key_pins = KeyPins::new().with_pins("a.test", [SERVER_PIN])?;
+        let client = HyperClient::try_from(config.clone().set_key_pins(Some(key_pins)))?;
+        let mut res = client
+            .send(Request::new(Method::Get, url("a.test")?))
+            .await?;
+        assert_eq!(res.body_string().await?, "ok");
+
+        let key_pins = KeyPins::new().with_pins("a.test", [OTHER_PIN])?;
+        let client = HyperClient::try_from(config.set_key_pins(Some(key_pins)))?;
+        let err = client
+            .send(Request::new(Method::Get, url("a.test")?))
+            .await
+            .unwrap_err();
+        let kind = ClientError::of(&err).unwrap().kind();
+        assert_eq!(kind, ClientErrorKind::PinnedKeyMismatch);
+
+        let mut res = client
+            .send(Request::new(Method::Get, url("b.test")?))
+            .await?;
+        assert_eq!(res.body_string().await?, "ok");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reports_tls_info() -> http_types::Result<()> {
+        let (addr, _) = spawn_tls_origin(false).await;
+        let resolver = StaticResolver::new().with_host("a.test", vec![addr.ip()]);
+        let tls = TlsSettings::new().add_root_certificate(Certificate::from_pem(CA));
+        let config = Config::new()
+            .set_resolver(Some(Arc::new(resolver)))
+            .set_tls(tls);
+        let client = HyperClient::try_from(config)?;
+
+        let url = Url::parse(&format!("https://a.test:{}/", addr.port()))?;
+        let res = client.send(Request::new(Method::Get, url)).await?;
+        let info = res.ext().get::<TlsInfo>().unwrap();
+        assert_eq!(info.peer_certificates().len(), 1);
+        assert_eq!(info.version(), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resolves_with_config_resolver() -> http_types::Result<()> {
+        let origin = spawn_origin().await;
+        let resolver = StaticResolver::new().with_host("origin.test", vec![origin.ip()]);
+        let client = HyperClient::try_from(Config::new().set_resolver(Some(Arc::new(resolver))))?;
+
+        let url = Url::parse(&format!("http://origin.test:{}/hello", origin.port()))?;
+        let mut res = client.send(Request::new(Method::Get, url)).await?;
+        assert_eq!(res.body_string().await?, "GET /hello HTTP/1.1");
+
+        let url = Url::parse(&format!("http://other.test:{}/", origin.port()))?;
+        let err = client
+            .send(Request::new(Method::Get, url))
+            .await
+            .unwrap_err();
+        assert_eq!(ClientError::of(&err).unwrap().kind(), ClientErrorKind::Dns);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reports_what_kind_of_failure() -> http_types::Result<()> {
+        // Nothing listens on a port once its listener is dropped.
+        let port = std::net::TcpListener::bind(("127.0.0.1", 0))?
+            .local_addr()?
+            .port();
+
+        let cases = vec![
+            (
+                "http://nonexistent.invalid/".to_string(),
+                ClientErrorKind::Dns,
+            ),
+            (
+                format!("http://127.0.0.1:{}/", port),
+                ClientErrorKind::Connect,
+            ),
+            (
+                "ftp://example.com/".to_string(),
+                ClientErrorKind::InvalidUrl,
+            ),
+        ];
+        for (url, kind) in cases {
+            let req = Request::new(Method::Get, Url::parse(&url)?);
+            let err = HyperClient::new().send(req).await.unwrap_err();
+            assert_eq!(ClientError::of(&err).unwrap().kind(), kind, "{}", url);
+        }
+        Ok(())
+    }
+}
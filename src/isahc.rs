@@ -1,12 +1,19 @@
 //! http-client implementation for isahc
 
 use std::convert::TryFrom;
+use std::io;
+use std::time::Duration;
 
 use async_std::io::BufReader;
-use isahc::config::Configurable;
-use isahc::{http, ResponseExt};
+use isahc::auth::{Authentication, Credentials};
+use isahc::config::{CaCertificate, ClientCertificate, Configurable, PrivateKey, RedirectPolicy};
+use isahc::{http, HttpClientBuilder, ResponseExt};
 
-use crate::Config;
+use crate::error::{body_read_errors, classify};
+use crate::middleware::FollowsRedirects;
+use crate::timeout::{self, timeout};
+use crate::tls::IdentityKind;
+use crate::{Certificate, ClientError, ClientErrorKind, Config, TimeoutError, TimeoutKind};
 
 use super::{async_trait, Body, Error, HttpClient, Request, Response};
 
@@ -41,36 +48,87 @@ impl IsahcClient {
 #[async_trait]
 impl HttpClient for IsahcClient {
     async fn send(&self, mut req: Request) -> Result<Response, Error> {
+        #[cfg(feature = "decompression")]
+        if self.config.decompress {
+            crate::decompress::prepare(&mut req);
+        }
+
         let mut builder = http::Request::builder()
             .uri(req.url().as_str())
             .method(http::Method::from_bytes(req.method().to_string().as_bytes()).unwrap());
 
+        // `Config::decompress` decompresses responses the same way as on other backends.
+        #[cfg(feature = "decompression")]
+        if self.config.decompress {
+            builder = builder.automatic_decompression(false);
+        }
+
         for (name, value) in req.iter() {
             builder = builder.header(name.as_str(), value.as_str());
         }
 
+        // Redirects are left to `middleware::Redirect` when it is in use, like on other backends.
+        if req.ext().get::<FollowsRedirects>().is_some() {
+            builder = builder.redirect_policy(RedirectPolicy::None);
+        }
+
+        // Proxies set in `Config` are chosen the same way as on other backends. Without one, curl
+        // still reads its proxy environment variables, unless `Config::no_proxy` lists the host.
+        builder = match self.config.proxy_for(req.url()) {
+            Some(proxy) => {
+                let mut url = proxy.url().clone();
+                // The URL has a host, so removing its credentials cannot fail.
+                let _ = url.set_username("");
+                let _ = url.set_password(None);
+                let uri = url
+                    .as_str()
+                    .parse::<http::Uri>()
+                    .map_err(|e| ClientError::new(ClientErrorKind::InvalidUrl, e).into_error())?;
+                builder = builder.proxy(Some(uri));
+
+                match proxy.credentials() {
+                    Some((username, password)) => builder
+                        .proxy_authentication(Authentication::basic())
+                        .proxy_credentials(Credentials::new(username, password)),
+                    None => builder,
+                }
+            }
+            None => match &self.config.no_proxy {
+                Some(no_proxy) if no_proxy.matches(req.url()) => builder.proxy(None),
+                _ => builder,
+            },
+        };
+
         let body = req.take_body();
         let body = match body.len() {
             Some(len) => isahc::Body::from_reader_sized(body, len as u64),
             None => isahc::Body::from_reader(body),
         };
 
-        let request = builder.body(body).unwrap();
-        let res = self.client.send_async(request).await.map_err(Error::from)?;
-        let maybe_metrics = res.metrics().cloned();
-        let (parts, body) = res.into_parts();
-        let body = Body::from_reader(BufReader::new(body), None);
-        let mut response = http_types::Response::new(parts.status.as_u16());
-        for (name, value) in &parts.headers {
-            response.append_header(name.as_str(), value.to_str().unwrap());
+        // curl only times connecting and the TLS handshake together. The other timeouts are
+        // applied by `timeout`, the same way as on other backends.
+        let connect_timeout = self
+            .config
+            .connect_timeout
+            .map(|timeout| timeout + self.config.tls_handshake_timeout.unwrap_or_default());
+        if let Some(timeout) = connect_timeout {
+            builder = builder.connect_timeout(timeout);
         }
 
-        if let Some(metrics) = maybe_metrics {
-            response.ext_mut().insert(metrics);
+        let request = builder.body(body).unwrap();
+        let response = timeout::send(&self.config, self.request(request, connect_timeout))
+            .await
+            .map_err(|e| classify(e, ClientErrorKind::Other))?;
+
+        #[cfg(feature = "decompression")]
+        if self.config.decompress {
+            return Ok(body_read_errors(crate::decompress::decode(
+                req.method(),
+                response,
+            )));
         }
 
-        response.set_body(body);
-        Ok(response)
+        Ok(body_read_errors(response))
     }
 
     /// Override the existing configuration with new configuration.
@@ -86,9 +144,10 @@ impl HttpClient for IsahcClient {
         if config.tcp_no_delay {
             builder = builder.tcp_nodelay();
         }
-        if let Some(timeout) = config.timeout {
-            builder = builder.timeout(timeout);
+        if let Some(ttl) = config.pool_idle_timeout {
+            builder = builder.connection_cache_ttl(ttl);
         }
+        builder = configure_tls(builder, &config)?;
 
         self.client = builder.build()?;
         self.config = config;
@@ -102,6 +161,140 @@ impl HttpClient for IsahcClient {
     }
 }
 
+impl IsahcClient {
+    /// Send `request`, within the response header timeout.
+    async fn request(
+        &self,
+        request: http::Request<isahc::Body>,
+        connect_timeout: Option<Duration>,
+    ) -> Result<Response, Error> {
+        let duration = self.config.response_header_timeout.or(self.config.timeout);
+        let send = self.client.send_async(request);
+        let res = timeout(TimeoutKind::ResponseHeader, duration, send)
+            .await?
+            .map_err(|e| into_error(e, connect_timeout))?;
+
+        let maybe_metrics = res.metrics().cloned();
+        let (parts, body) = res.into_parts();
+        let body = Body::from_reader(BufReader::new(body), None);
+        let mut response = http_types::Response::new(parts.status.as_u16());
+        for (name, value) in &parts.headers {
+            response.append_header(name.as_str(), value.to_str().unwrap());
+        }
+
+        if let Some(metrics) = maybe_metrics {
+            response.ext_mut().insert(metrics);
+        }
+
+        response.set_body(body);
+        Ok(response)
+    }
+}
+
+/// Convert an isahc error, with the kind its curl error code corresponds to.
+fn into_error(err: isahc::Error, connect_timeout: Option<Duration>) -> Error {
+    let kind = match &err {
+        isahc::Error::CouldntResolveHost | isahc::Error::CouldntResolveProxy => {
+            ClientErrorKind::Dns
+        }
+        isahc::Error::ConnectFailed => ClientErrorKind::Connect,
+        isahc::Error::BadClientCertificate(_)
+        | isahc::Error::BadServerCertificate(_)
+        | isahc::Error::SSLConnectFailed(_)
+        | isahc::Error::SSLEngineError(_) => ClientErrorKind::Tls,
+        // The only timeout curl is given is the connect timeout.
+        isahc::Error::Timeout => match connect_timeout {
+            Some(duration) => {
+                return ClientError::from(TimeoutError::new(TimeoutKind::Connect, duration))
+                    .into_error()
+            }
+            None => ClientErrorKind::Other,
+        },
+        isahc::Error::InvalidHttpFormat(_) => ClientErrorKind::InvalidUrl,
+        isahc::Error::InvalidContentEncoding(_) | isahc::Error::NoResponse => {
+            ClientErrorKind::Protocol
+        }
+        isahc::Error::RequestBodyError(_) | isahc::Error::Io(_) => ClientErrorKind::Io,
+        isahc::Error::ResponseBodyError(_) => ClientErrorKind::BodyRead,
+        isahc::Error::TooManyRedirects => ClientErrorKind::TooManyRedirects,
+        _ => ClientErrorKind::Other,
+    };
+    ClientError::new(kind, err).into_error()
+}
+
+/// Give `builder` the client identity and root certificate in `config`, which curl only reads
+/// from files. curl has no setting for TLS versions, and no way to check key pins per host.
+fn configure_tls(
+    mut builder: HttpClientBuilder,
+    config: &Config,
+) -> Result<HttpClientBuilder, isahc::Error> {
+    let unsupported =
+        |msg: &str| isahc::Error::Io(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    if config.key_pins.is_some() {
+        return Err(unsupported("curl_client does not support key pins"));
+    }
+
+    let tls = &config.tls;
+    // isahc has no option for the protocol version, so it is refused rather than left to curl.
+    if tls.min_version.is_some() || tls.max_version.is_some() {
+        return Err(unsupported(
+            "curl_client does not support TLS version limits",
+        ));
+    }
+
+    match tls.identity.as_ref().map(|identity| &identity.kind) {
+        Some(IdentityKind::Pem {
+            paths: Some((certs, key)),
+            ..
+        }) => {
+            let key = PrivateKey::pem_file(key, None);
+            builder = builder.ssl_client_certificate(ClientCertificate::pem_file(certs, key));
+        }
+        Some(IdentityKind::Pkcs12 {
+            path: Some(path),
+            password,
+            ..
+        }) => {
+            let certificate = ClientCertificate::p12_file(path, password.clone());
+            builder = builder.ssl_client_certificate(certificate);
+        }
+        Some(_) => return Err(unsupported("curl_client only reads identities from files")),
+        None => {}
+    }
+
+    // curl trusts either its built-in roots or the file it is given, never both.
+    match (tls.built_in_roots, tls.root_certificates.as_slice()) {
+        (true, []) => {}
+        (false, []) => {
+            return Err(unsupported(
+                "curl_client needs a root certificate when the built-in roots are disabled",
+            ))
+        }
+        (true, [_, ..]) => {
+            return Err(unsupported(
+                "curl_client only trusts a root certificate instead of the built-in roots",
+            ))
+        }
+        (
+            false,
+            [Certificate {
+                path: Some(path), ..
+            }],
+        ) => builder = builder.ssl_ca_certificate(CaCertificate::file(path)),
+        (false, [_]) => {
+            return Err(unsupported(
+                "curl_client only reads root certificates from files",
+            ))
+        }
+        _ => {
+            return Err(unsupported(
+                "curl_client only supports one root certificate",
+            ))
+        }
+    }
+    Ok(builder)
+}
+
 impl TryFrom<Config> for IsahcClient {
     type Error = isahc::Error;
 
@@ -114,9 +307,10 @@ impl TryFrom<Config> for IsahcClient {
         if config.tcp_no_delay {
             builder = builder.tcp_nodelay();
         }
-        if let Some(timeout) = config.timeout {
-            builder = builder.timeout(timeout);
+        if let Some(ttl) = config.pool_idle_timeout {
+            builder = builder.connection_cache_ttl(ttl);
         }
+        builder = configure_tls(builder, &config)?;
 
         Ok(Self {
             client: builder.build()?,
@@ -128,6 +322,7 @@ impl TryFrom<Config> for IsahcClient {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{KeyPins, TlsSettings, TlsVersion};
     use async_std::prelude::*;
     use async_std::task;
     use http_types::url::Url;
@@ -169,4 +364,42 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn reads_certificates_from_files() -> Result<()> {
+        let ca = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/ca.pem");
+        let tls = TlsSettings::new()
+            .set_built_in_roots(false)
+            .add_root_certificate(Certificate::from_pem_file(ca)?);
+        IsahcClient::try_from(Config::new().set_tls(tls))?;
+
+        let tls = TlsSettings::new()
+            .set_built_in_roots(false)
+            .add_root_certificate(Certificate::from_pem(b""));
+        assert!(IsahcClient::try_from(Config::new().set_tls(tls)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_settings_curl_cannot_apply() -> Result<()> {
+        let ca = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/ca.pem");
+        let refused = [
+            TlsSettings::new().set_min_version(Some(TlsVersion::Tls1_2)),
+            TlsSettings::new().set_max_version(Some(TlsVersion::Tls1_2)),
+            TlsSettings::new().set_built_in_roots(false),
+            TlsSettings::new().add_root_certificate(Certificate::from_pem_file(ca)?),
+        ];
+        for tls in refused {
+            let err = IsahcClient::try_from(Config::new().set_tls(tls)).unwrap_err();
+            assert!(matches!(err, isahc::Error::Io(e) if e.kind() == io::ErrorKind::InvalidInput));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_key_pins() {
+        let key_pins = KeyPins::new().with_host("example.com", vec![[0; 32]]);
+        let config = Config::new().set_key_pins(Some(key_pins));
+        assert!(IsahcClient::try_from(config).is_err());
+    }
 }
@@ -17,6 +17,55 @@
 mod config;
 pub use config::Config;
 
+mod error;
+pub use error::{ClientError, ClientErrorKind, TimeoutError, TimeoutKind};
+
+mod proxy;
+pub use proxy::{NoProxy, Proxy};
+
+mod resolve;
+#[cfg(any(feature = "h1_client", feature = "hyper_client"))]
+pub use resolve::SystemResolver;
+pub use resolve::{FixedLifetimeResolver, Resolve, StaticResolver};
+
+#[cfg(any(
+    feature = "h1_client",
+    feature = "hyper_client",
+    all(feature = "curl_client", not(target_arch = "wasm32")),
+    all(feature = "wasm_client", target_arch = "wasm32")
+))]
+mod timeout;
+
+// Only the backends read the settings, and `curl_client` only reads their paths.
+#[cfg_attr(
+    not(any(feature = "h1_client", feature = "hyper_client")),
+    allow(dead_code)
+)]
+mod tls;
+pub use tls::{Certificate, Identity, KeyPins, TlsInfo, TlsSettings, TlsVersion};
+
+// Only `wasm_client` reads the options.
+#[cfg_attr(
+    not(all(feature = "wasm_client", target_arch = "wasm32")),
+    allow(dead_code)
+)]
+mod fetch;
+pub use fetch::{
+    FetchCache, FetchCredentials, FetchMode, FetchOptions, FetchRedirect, ReferrerPolicy,
+};
+
+pub mod middleware;
+
+#[cfg(all(
+    feature = "decompression",
+    any(
+        feature = "h1_client",
+        feature = "hyper_client",
+        feature = "curl_client"
+    )
+))]
+mod decompress;
+
 #[cfg_attr(feature = "docs", doc(cfg(feature = "curl_client")))]
 #[cfg(all(feature = "curl_client", not(target_arch = "wasm32")))]
 pub mod isahc;
@@ -31,13 +80,21 @@ pub mod native;
 
 #[cfg_attr(feature = "docs", doc(cfg(feature = "h1_client")))]
 #[cfg_attr(feature = "docs", doc(cfg(feature = "default")))]
-#[cfg(any(feature = "h1_client", feature = "h1_client_rustls"))]
+#[cfg(feature = "h1_client")]
 pub mod h1;
 
 #[cfg_attr(feature = "docs", doc(cfg(feature = "hyper_client")))]
 #[cfg(feature = "hyper_client")]
 pub mod hyper;
 
+#[cfg(all(test, any(feature = "h1_client", feature = "hyper_client")))]
+// The TLS servers are only used with a TLS stack.
+#[cfg_attr(
+    not(any(feature = "hyper_client", feature = "native-tls", feature = "rustls")),
+    allow(dead_code)
+)]
+mod test_servers;
+
 /// An HTTP Request type with a streaming body.
 pub type Request = http_types::Request;
 
@@ -0,0 +1,524 @@
+//! Cookie middleware.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use http_types::cookies::Cookie;
+use http_types::headers::{COOKIE, SET_COOKIE};
+use http_types::url::{Host, Url};
+
+use super::{now, Middleware, Next};
+use crate::{async_trait, Error, Request, Response};
+
+/// Middleware which stores cookies from responses and sends them with later requests.
+///
+/// Cookies are stored and matched following the rules of
+/// [RFC 6265](https://tools.ietf.org/html/rfc6265): a cookie is only sent to the host (and, if it
+/// has a `Domain` attribute, the subdomains) and path it applies to, until it expires, and
+/// `Secure` cookies are only sent over `https`.
+///
+/// A jar is either kept in memory, or backed by a file in the Netscape `cookies.txt` format
+/// understood by `curl` and `wget`. A file-backed jar is written to by [`save`](CookieJar::save),
+/// and when the last handle to it is dropped if its cookies changed. Session cookies, which have
+/// no expiry time, are never written to the file.
+///
+/// Cookies with a `Domain` attribute naming a public suffix, such as `com` or `co.uk`, are
+/// rejected, unless the public suffix is the host which set them, which is then the only host they
+/// are sent to.
+///
+/// Cloning a jar returns a new handle to the same cookies, so a jar can be inspected while it is
+/// in use by a client.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "h1_client")]
+/// # {
+/// use http_client::h1::H1Client;
+/// use http_client::middleware::{ClientWithMiddleware, CookieJar};
+///
+/// let jar = CookieJar::open("cookies.txt")?;
+/// let client = ClientWithMiddleware::new(H1Client::new()).with(jar.clone());
+/// # }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CookieJar {
+    store: Arc<Mutex<Store>>,
+}
+
+#[derive(Debug, Default)]
+struct Store {
+    cookies: Vec<StoredCookie>,
+    path: Option<PathBuf>,
+    /// Whether the cookies changed since they were last written to the file.
+    dirty: bool,
+    /// Incremented for every new cookie, to order cookies by creation.
+    next_id: u64,
+}
+
+#[derive(Clone, Debug)]
+struct StoredCookie {
+    name: String,
+    value: String,
+    domain: String,
+    host_only: bool,
+    path: String,
+    secure: bool,
+    http_only: bool,
+    expires: Option<SystemTime>,
+    id: u64,
+}
+
+impl CookieJar {
+    /// Create a new, empty, in-memory cookie jar.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open a cookie jar backed by the file at `path`.
+    ///
+    /// The cookies in the file are loaded if it exists. It is created when cookies are first
+    /// stored otherwise.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let mut store = Store::default();
+        match fs::read_to_string(&path) {
+            Ok(contents) => store.load(&contents),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        store.path = Some(path);
+
+        Ok(Self {
+            store: Arc::new(Mutex::new(store)),
+        })
+    }
+
+    /// Write the cookies to the backing file, if there is one.
+    ///
+    /// This blocks the current thread on the file system.
+    pub fn save(&self) -> io::Result<()> {
+        self.lock().save()
+    }
+
+    /// Store a cookie from a `Set-Cookie` header received in a response to `url`.
+    ///
+    /// Returns `false` if the cookie was rejected, for example because it is malformed or its
+    /// `Domain` attribute does not match `url` or names a public suffix.
+    pub fn store(&self, url: &Url, set_cookie: &str) -> bool {
+        let mut store = self.lock();
+        let changed = store.store(url, set_cookie, now());
+        store.dirty |= changed;
+        changed
+    }
+
+    /// The cookies which would be sent with a request to `url`, in the order they are sent.
+    pub fn cookies(&self, url: &Url) -> Vec<Cookie<'static>> {
+        self.lock()
+            .matching(url, now())
+            .into_iter()
+            .map(|c| Cookie::new(c.name.clone(), c.value.clone()))
+            .collect()
+    }
+
+    /// The value of the `Cookie` header which would be sent with a request to `url`, if any.
+    pub fn cookie_header(&self, url: &Url) -> Option<String> {
+        let store = self.lock();
+        let cookies = store.matching(url, now());
+        if cookies.is_empty() {
+            return None;
+        }
+
+        let mut header = String::new();
+        for (i, cookie) in cookies.iter().enumerate() {
+            if i > 0 {
+                header.push_str("; ");
+            }
+            let _ = write!(header, "{}={}", cookie.name, cookie.value);
+        }
+        Some(header)
+    }
+
+    /// Remove all cookies from the jar.
+    pub fn clear(&self) {
+        let mut store = self.lock();
+        store.dirty |= !store.cookies.is_empty();
+        store.cookies.clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Store> {
+        // A panic while holding the lock cannot leave the store in an inconsistent state.
+        self.store.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl Middleware for CookieJar {
+    async fn handle(&self, mut req: Request, next: Next<'_>) -> Result<Response, Error> {
+        let url = req.url().clone();
+        if let Some(cookies) = self.cookie_header(&url) {
+            let header = match req.header(COOKIE) {
+                Some(existing) => format!("{}; {}", existing.last(), cookies),
+                None => cookies,
+            };
+            req.insert_header(COOKIE, header);
+        }
+
+        let res = next.run(req).await?;
+
+        if let Some(set_cookies) = res.header(SET_COOKIE) {
+            for set_cookie in set_cookies {
+                self.store(&url, set_cookie.as_str());
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+impl Store {
+    /// Store a cookie following RFC 6265, section 5.3.
+    fn store(&mut self, url: &Url, set_cookie: &str, now: SystemTime) -> bool {
+        let cookie = match Cookie::parse(set_cookie) {
+            Ok(cookie) => cookie,
+            Err(_) => return false,
+        };
+        let host = match url.host_str() {
+            Some(host) => host.trim_end_matches('.').to_ascii_lowercase(),
+            None => return false,
+        };
+
+        let (domain, host_only) = match cookie.domain() {
+            Some(domain) if !domain.is_empty() => {
+                let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+                if !domain_match(&host, &domain, is_ip(url)) {
+                    return false;
+                }
+                // A cookie for a public suffix would be sent to every site under it, so it is
+                // only kept for the host which set it (section 5.3, step 5).
+                if !is_ip(url) && psl::suffix_str(&domain) == Some(domain.as_str()) {
+                    if domain != host {
+                        return false;
+                    }
+                    (host, true)
+                } else {
+                    (domain, false)
+                }
+            }
+            _ => (host, true),
+        };
+
+        let path = match cookie.path() {
+            Some(path) if path.starts_with('/') => path.to_string(),
+            _ => default_path(url),
+        };
+
+        let secure = cookie.secure().unwrap_or(false);
+        if secure && url.scheme() != "https" {
+            return false;
+        }
+
+        let expires = match (cookie.max_age(), cookie.expires()) {
+            (Some(max_age), _) => match max_age.whole_seconds() {
+                secs if secs <= 0 => Some(UNIX_EPOCH),
+                secs => Some(now + Duration::from_secs(secs as u64)),
+            },
+            (None, Some(expires)) => Some(SystemTime::from(expires)),
+            (None, None) => None,
+        };
+
+        let existing = self
+            .cookies
+            .iter()
+            .position(|c| c.name == cookie.name() && c.domain == domain && c.path == path);
+        let id = match existing {
+            Some(idx) => self.cookies.remove(idx).id,
+            None => {
+                self.next_id += 1;
+                self.next_id
+            }
+        };
+
+        if matches!(expires, Some(expires) if expires <= now) {
+            return existing.is_some();
+        }
+
+        self.cookies.push(StoredCookie {
+            name: cookie.name().to_string(),
+            value: cookie.value().to_string(),
+            domain,
+            host_only,
+            path,
+            secure,
+            http_only: cookie.http_only().unwrap_or(false),
+            expires,
+            id,
+        });
+        true
+    }
+
+    /// The cookies to send to `url`, following RFC 6265, section 5.4.
+    fn matching(&self, url: &Url, now: SystemTime) -> Vec<&StoredCookie> {
+        let host = match url.host_str() {
+            Some(host) => host.trim_end_matches('.').to_ascii_lowercase(),
+            None => return vec![],
+        };
+        let secure = url.scheme() == "https";
+
+        let mut cookies: Vec<_> = self
+            .cookies
+            .iter()
+            .filter(|c| {
+                if c.host_only {
+                    host == c.domain
+                } else {
+                    domain_match(&host, &c.domain, is_ip(url))
+                }
+            })
+            .filter(|c| path_match(url.path(), &c.path))
+            .filter(|c| secure || !c.secure)
+            .filter(|c| !matches!(c.expires, Some(expires) if expires <= now))
+            .collect();
+
+        cookies.sort_by(|a, b| b.path.len().cmp(&a.path.len()).then(a.id.cmp(&b.id)));
+        cookies
+    }
+
+    /// Write the cookies to the backing file, if there is one.
+    fn save(&mut self) -> io::Result<()> {
+        if let Some(path) = &self.path {
+            write_file(path, &self.serialize())?;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Load cookies in the Netscape `cookies.txt` format.
+    fn load(&mut self, contents: &str) {
+        for line in contents.lines() {
+            let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<_> = line.split('\t').collect();
+            if fields.len() != 7 {
+                log::debug!("skipping malformed cookie file line: {:?}", line);
+                continue;
+            }
+
+            let expires = match fields[4].parse::<u64>() {
+                Ok(0) => None,
+                Ok(secs) => Some(UNIX_EPOCH + Duration::from_secs(secs)),
+                Err(_) => continue,
+            };
+
+            self.next_id += 1;
+            self.cookies.push(StoredCookie {
+                name: fields[5].to_string(),
+                value: fields[6].to_string(),
+                domain: fields[0].trim_start_matches('.').to_ascii_lowercase(),
+                host_only: fields[1] != "TRUE",
+                path: fields[2].to_string(),
+                secure: fields[3] == "TRUE",
+                http_only,
+                expires,
+                id: self.next_id,
+            });
+        }
+    }
+
+    /// Serialize persistent cookies in the Netscape `cookies.txt` format.
+    fn serialize(&self) -> String {
+        let mut contents = String::from("# Netscape HTTP Cookie File\n");
+        // In order of creation, which is kept when the file is loaded.
+        let mut cookies: Vec<_> = self.cookies.iter().collect();
+        cookies.sort_by_key(|c| c.id);
+        for cookie in cookies {
+            let expires = match cookie.expires {
+                Some(expires) => expires,
+                None => continue,
+            };
+            let expires = expires
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+
+            let _ = writeln!(
+                contents,
+                "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                if cookie.http_only { "#HttpOnly_" } else { "" },
+                if cookie.host_only { "" } else { "." },
+                cookie.domain,
+                if cookie.host_only { "FALSE" } else { "TRUE" },
+                cookie.path,
+                if cookie.secure { "TRUE" } else { "FALSE" },
+                expires,
+                cookie.name,
+                cookie.value,
+            );
+        }
+        contents
+    }
+}
+
+impl Drop for Store {
+    fn drop(&mut self) {
+        if self.dirty {
+            if let Err(e) = self.save() {
+                log::warn!("failed to save cookies: {}", e);
+            }
+        }
+    }
+}
+
+fn write_file(path: &Path, contents: &str) -> io::Result<()> {
+    // Write to a temporary file first so a failed write cannot truncate the jar.
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+fn is_ip(url: &Url) -> bool {
+    matches!(url.host(), Some(Host::Ipv4(_)) | Some(Host::Ipv6(_)))
+}
+
+/// RFC 6265, section 5.1.3.
+fn domain_match(host: &str, domain: &str, host_is_ip: bool) -> bool {
+    if host == domain {
+        return true;
+    }
+    !host_is_ip && host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.')
+}
+
+/// RFC 6265, section 5.1.4.
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+/// The default cookie path of `url`, RFC 6265, section 5.1.4.
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(idx) => path[..idx].to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn domain_and_path_matching() {
+        let jar = CookieJar::new();
+        assert!(jar.store(&url("http://example.com/a/b"), "host=1"));
+        assert!(jar.store(
+            &url("http://www.example.com/"),
+            "domain=2; Domain=.example.com; Path=/docs"
+        ));
+        assert!(!jar.store(&url("http://example.com/"), "other=3; Domain=other.com"));
+        assert!(!jar.store(&url("http://example.com/"), "tld=4; Domain=com"));
+        assert!(!jar.store(&url("http://example.co.uk/"), "suffix=5; Domain=co.uk"));
+        assert!(jar.store(&url("http://localhost/"), "local=6; Domain=localhost"));
+
+        assert_eq!(
+            jar.cookie_header(&url("http://example.com/a/c")).as_deref(),
+            Some("host=1")
+        );
+        assert_eq!(jar.cookie_header(&url("http://www.example.com/a")), None);
+        assert_eq!(
+            jar.cookie_header(&url("http://api.example.com/docs/x"))
+                .as_deref(),
+            Some("domain=2")
+        );
+        assert_eq!(jar.cookie_header(&url("http://example.com/docsx")), None);
+        assert_eq!(jar.cookie_header(&url("http://other.co.uk/")), None);
+        assert_eq!(
+            jar.cookie_header(&url("http://localhost/")).as_deref(),
+            Some("local=6")
+        );
+    }
+
+    #[test]
+    fn secure_and_expiry() {
+        let jar = CookieJar::new();
+        assert!(!jar.store(&url("http://example.com/"), "a=1; Secure"));
+        assert!(jar.store(&url("https://example.com/"), "a=1; Secure"));
+        assert!(jar.store(&url("https://example.com/"), "b=2; Max-Age=60"));
+        assert!(jar.store(&url("https://example.com/"), "c=3"));
+
+        assert_eq!(
+            jar.cookie_header(&url("http://example.com/")).as_deref(),
+            Some("b=2; c=3")
+        );
+        assert_eq!(
+            jar.cookie_header(&url("https://example.com/")).as_deref(),
+            Some("a=1; b=2; c=3")
+        );
+
+        assert!(jar.store(&url("https://example.com/"), "b=2; Max-Age=0"));
+        assert!(jar.store(
+            &url("https://example.com/"),
+            "c=3; Expires=Wed, 21 Oct 2015 07:28:00 GMT"
+        ));
+        assert_eq!(
+            jar.cookie_header(&url("https://example.com/")).as_deref(),
+            Some("a=1")
+        );
+    }
+
+    #[test]
+    fn persists_to_file() -> io::Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("http-client-cookies-{}.txt", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let jar = CookieJar::open(&path)?;
+        jar.store(&url("https://example.com/"), "session=1");
+        jar.store(
+            &url("https://example.com/"),
+            "persistent=2; Max-Age=3600; HttpOnly",
+        );
+        jar.store(
+            &url("https://www.example.com/"),
+            "shared=3; Max-Age=3600; Domain=example.com",
+        );
+        jar.save()?;
+        let jar = CookieJar::open(&path)?;
+        assert_eq!(
+            jar.cookie_header(&url("https://example.com/")).as_deref(),
+            Some("persistent=2; shared=3")
+        );
+
+        // Changed cookies are written when the last handle is dropped.
+        jar.store(&url("https://example.com/"), "persistent=4; Max-Age=3600");
+        drop(jar);
+        let jar = CookieJar::open(&path)?;
+        fs::remove_file(&path)?;
+        assert_eq!(
+            jar.cookie_header(&url("https://example.com/")).as_deref(),
+            Some("persistent=4; shared=3")
+        );
+        assert_eq!(
+            jar.cookie_header(&url("https://www.example.com/"))
+                .as_deref(),
+            Some("shared=3")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,299 @@
+//! Middleware types.
+//!
+//! Middleware wraps an [`HttpClient`] and can inspect or modify every request on its way out, and
+//! every response on its way back in. Layers are stacked with [`ClientWithMiddleware::with`] and
+//! run in the order they were added, so the first middleware added sees the request first and the
+//! response last.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # #[cfg(feature = "h1_client")]
+//! # {
+//! use http_client::h1::H1Client;
+//! use http_client::middleware::{ClientWithMiddleware, Middleware, Next};
+//! use http_client::{async_trait, Error, Request, Response};
+//!
+//! #[derive(Debug)]
+//! struct Logger;
+//!
+//! #[async_trait]
+//! impl Middleware for Logger {
+//!     async fn handle(&self, req: Request, next: Next<'_>) -> Result<Response, Error> {
+//!         println!("sending request to {}", req.url());
+//!         let res = next.run(req).await?;
+//!         println!("received response with status {}", res.status());
+//!         Ok(res)
+//!     }
+//! }
+//!
+//! let client = ClientWithMiddleware::new(H1Client::new()).with(Logger);
+//! # }
+//! ```
+
+use std::fmt::{self, Debug};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::{async_trait, Config, Error, HttpClient, Request, Response};
+
+#[cfg(any(not(target_arch = "wasm32"), feature = "wasm_client"))]
+mod cookies;
+mod redirect;
+mod replay;
+mod retry;
+
+#[cfg(any(not(target_arch = "wasm32"), feature = "wasm_client"))]
+pub use cookies::CookieJar;
+#[cfg(all(feature = "curl_client", not(target_arch = "wasm32")))]
+pub(crate) use redirect::FollowsRedirects;
+pub use redirect::{Redirect, RedirectAttempt, RedirectChain, RedirectPolicy};
+pub use retry::RetryPolicy;
+
+/// An owned dynamically typed [`Future`] for use in cases where you can't statically type your
+/// result or need to add some indirection.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Middleware that wraps around the remaining middleware chain and the backing client.
+#[async_trait]
+pub trait Middleware: Send + Sync + 'static {
+    /// Asynchronously handle the request, and return a response.
+    async fn handle(&self, req: Request, next: Next<'_>) -> Result<Response, Error>;
+
+    /// Return the name of the middleware, used in `Debug` output.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+}
+
+#[async_trait]
+impl<F> Middleware for F
+where
+    F: Send
+        + Sync
+        + 'static
+        + for<'a> Fn(Request, Next<'a>) -> BoxFuture<'a, Result<Response, Error>>,
+{
+    async fn handle(&self, req: Request, next: Next<'_>) -> Result<Response, Error> {
+        (self)(req, next).await
+    }
+}
+
+/// The remainder of a middleware chain, including the backing client.
+///
+/// `Next` is cheap to copy, so middleware that needs to send more than one request (for example
+/// to retry or to follow a redirect) can run it several times.
+#[derive(Copy, Clone)]
+pub struct Next<'a> {
+    client: &'a dyn HttpClient,
+    middleware: &'a [Arc<dyn Middleware>],
+}
+
+impl Debug for Next<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Next")
+            .field("client", &self.client)
+            .field("middleware", &MiddlewareNames(self.middleware))
+            .finish()
+    }
+}
+
+impl<'a> Next<'a> {
+    /// Create a new instance.
+    pub fn new(client: &'a dyn HttpClient, middleware: &'a [Arc<dyn Middleware>]) -> Self {
+        Self { client, middleware }
+    }
+
+    /// Asynchronously execute the remaining middleware chain.
+    pub async fn run(self, req: Request) -> Result<Response, Error> {
+        match self.middleware.split_first() {
+            Some((current, middleware)) => {
+                let next = Next::new(self.client, middleware);
+                current.handle(req, next).await
+            }
+            None => self.client.send(req).await,
+        }
+    }
+
+    /// Get the configuration of the backing client.
+    pub fn config(&self) -> &'a Config {
+        self.client.config()
+    }
+}
+
+/// An [`HttpClient`] which runs every request through a stack of [`Middleware`].
+///
+/// `ClientWithMiddleware` is itself an `HttpClient`, so it can be used anywhere the backing
+/// client can, including as a `Box<dyn HttpClient>`.
+pub struct ClientWithMiddleware<C: HttpClient> {
+    client: C,
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl<C: HttpClient> Debug for ClientWithMiddleware<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientWithMiddleware")
+            .field("client", &self.client)
+            .field("middleware", &MiddlewareNames(&self.middleware))
+            .finish()
+    }
+}
+
+impl<C: HttpClient + Clone> Clone for ClientWithMiddleware<C> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            middleware: self.middleware.clone(),
+        }
+    }
+}
+
+impl<C: HttpClient> ClientWithMiddleware<C> {
+    /// Create a new instance wrapping `client`, without any middleware.
+    pub fn new(client: C) -> Self {
+        Self {
+            client,
+            middleware: Vec::new(),
+        }
+    }
+
+    /// Push middleware onto the middleware stack.
+    ///
+    /// Middleware runs in the order it was added.
+    pub fn with(mut self, middleware: impl Middleware) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Get a reference to the backing client.
+    pub fn inner(&self) -> &C {
+        &self.client
+    }
+
+    /// Get a mutable reference to the backing client.
+    pub fn inner_mut(&mut self) -> &mut C {
+        &mut self.client
+    }
+
+    /// Unwrap the backing client, dropping all middleware.
+    pub fn into_inner(self) -> C {
+        self.client
+    }
+}
+
+#[async_trait]
+impl<C: HttpClient> HttpClient for ClientWithMiddleware<C> {
+    async fn send(&self, req: Request) -> Result<Response, Error> {
+        Next::new(&self.client, &self.middleware).run(req).await
+    }
+
+    /// Override the configuration of the backing client.
+    fn set_config(&mut self, config: Config) -> http_types::Result<()> {
+        self.client.set_config(config)
+    }
+
+    /// Get the configuration of the backing client.
+    fn config(&self) -> &Config {
+        self.client.config()
+    }
+}
+
+struct MiddlewareNames<'a>(&'a [Arc<dyn Middleware>]);
+
+impl Debug for MiddlewareNames<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|m| m.name()))
+            .finish()
+    }
+}
+
+/// The current time.
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> std::time::SystemTime {
+    std::time::SystemTime::now()
+}
+
+/// The current time, from JavaScript, as `SystemTime::now` panics on `wasm32-unknown-unknown`.
+#[cfg(all(target_arch = "wasm32", feature = "wasm_client"))]
+fn now() -> std::time::SystemTime {
+    std::time::UNIX_EPOCH + std::time::Duration::from_millis(js_sys::Date::now() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_types::{Method, StatusCode, Url};
+
+    #[derive(Debug)]
+    struct EchoClient {
+        config: Config,
+    }
+
+    #[async_trait]
+    impl HttpClient for EchoClient {
+        async fn send(&self, mut req: Request) -> Result<Response, Error> {
+            let mut res = Response::new(StatusCode::Ok);
+            res.set_body(req.take_body());
+            for (name, values) in req.iter() {
+                res.insert_header(name, values);
+            }
+            Ok(res)
+        }
+
+        fn config(&self) -> &Config {
+            &self.config
+        }
+    }
+
+    #[derive(Debug)]
+    struct AppendHeader(&'static str);
+
+    #[async_trait]
+    impl Middleware for AppendHeader {
+        async fn handle(&self, mut req: Request, next: Next<'_>) -> Result<Response, Error> {
+            req.append_header("x-order", self.0);
+            let mut res = next.run(req).await?;
+            res.append_header("x-response-order", self.0);
+            Ok(res)
+        }
+    }
+
+    fn client() -> EchoClient {
+        EchoClient {
+            config: Config::new(),
+        }
+    }
+
+    #[async_std::test]
+    async fn runs_middleware_in_order() -> http_types::Result<()> {
+        let client = ClientWithMiddleware::new(client())
+            .with(AppendHeader("first"))
+            .with(AppendHeader("second"));
+
+        let req = Request::new(Method::Get, Url::parse("http://example.com/").unwrap());
+        let res = client.send(req).await?;
+
+        let order: Vec<_> = res["x-order"].iter().map(|v| v.as_str()).collect();
+        assert_eq!(order, ["first", "second"]);
+        let order: Vec<_> = res["x-response-order"].iter().map(|v| v.as_str()).collect();
+        assert_eq!(order, ["second", "first"]);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn closures_and_trait_objects() -> http_types::Result<()> {
+        fn short_circuit(_req: Request, _next: Next<'_>) -> BoxFuture<'_, Result<Response, Error>> {
+            Box::pin(async { Ok(Response::new(StatusCode::Accepted)) })
+        }
+
+        let client: Box<dyn HttpClient> =
+            Box::new(ClientWithMiddleware::new(client()).with(short_circuit));
+
+        let req = Request::new(Method::Get, Url::parse("http://example.com/").unwrap());
+        let res = client.send(req).await?;
+        assert_eq!(res.status(), StatusCode::Accepted);
+        Ok(())
+    }
+}
@@ -0,0 +1,417 @@
+//! Redirect middleware.
+
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+use http_types::headers::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, LOCATION};
+use http_types::url::Url;
+use http_types::{Method, StatusCode};
+
+use super::replay::{ReplayExtensions, ReplayableBody};
+use super::{Middleware, Next};
+use crate::{async_trait, ClientError, ClientErrorKind, Error, Request, Response};
+
+/// The largest request body, in bytes, which is buffered so it can be resent after a `307` or
+/// `308` redirect.
+const MAX_REPLAY_BODY_SIZE: usize = 64 * 1024;
+
+/// Controls which redirects are followed by [`Redirect`].
+#[derive(Clone)]
+pub enum RedirectPolicy {
+    /// Do not follow redirects, and return `3xx` responses as-is.
+    None,
+    /// Follow at most this many redirects, and fail with an error on the next one.
+    Limited(usize),
+    /// Follow a redirect only if the closure returns `true`.
+    Custom(Arc<dyn Fn(&RedirectAttempt<'_>) -> bool + Send + Sync + 'static>),
+}
+
+impl RedirectPolicy {
+    /// Create a policy which uses `f` to decide which redirects to follow.
+    pub fn custom(f: impl Fn(&RedirectAttempt<'_>) -> bool + Send + Sync + 'static) -> Self {
+        Self::Custom(Arc::new(f))
+    }
+}
+
+impl Default for RedirectPolicy {
+    /// Follow up to 10 redirects.
+    fn default() -> Self {
+        Self::Limited(10)
+    }
+}
+
+impl Debug for RedirectPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => f.write_str("None"),
+            Self::Limited(max) => f.debug_tuple("Limited").field(max).finish(),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// A redirect which is about to be followed, passed to [`RedirectPolicy::Custom`].
+#[derive(Debug)]
+pub struct RedirectAttempt<'a> {
+    status: StatusCode,
+    url: &'a Url,
+    previous: &'a [Url],
+}
+
+impl RedirectAttempt<'_> {
+    /// The status of the redirect response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The URL the redirect points to.
+    pub fn url(&self) -> &Url {
+        self.url
+    }
+
+    /// The URLs requested so far, starting with the original request.
+    pub fn previous(&self) -> &[Url] {
+        self.previous
+    }
+}
+
+/// The URLs requested while following redirects.
+///
+/// [`Redirect`] inserts this into the extensions of every response it returns.
+#[derive(Clone, Debug)]
+pub struct RedirectChain {
+    urls: Vec<Url>,
+}
+
+impl RedirectChain {
+    /// Every URL requested, starting with the original request and ending with the request which
+    /// produced the response.
+    pub fn urls(&self) -> &[Url] {
+        &self.urls
+    }
+
+    /// The URL of the request which produced the response.
+    pub fn final_url(&self) -> &Url {
+        // UNWRAP: the chain always starts with the original request URL.
+        self.urls.last().unwrap()
+    }
+
+    /// Whether any redirects were followed.
+    pub fn is_redirected(&self) -> bool {
+        self.urls.len() > 1
+    }
+}
+
+/// Middleware which follows redirects according to a [`RedirectPolicy`].
+///
+/// The same rules apply whichever backend is in use:
+///
+/// - `Authorization` and `Cookie` headers are removed when a redirect leads to another origin.
+/// - A `303 See Other` redirect is followed with a `GET` request without a body, as is a `301` or
+///   `302` redirect of a `POST` request.
+/// - `307` and `308` redirects keep the method and body. Request bodies larger than 64 KiB cannot
+///   be resent, so such redirects are returned as-is.
+///
+/// The URLs requested along the way are recorded in a [`RedirectChain`] response extension.
+///
+/// Requests sent for redirects carry the extensions of this crate, such as `h1::UnixSocket` and
+/// `FetchOptions`, and those added with [`replay_extension`](Redirect::replay_extension).
+///
+/// `curl_client` leaves redirects to this middleware, whatever the `isahc` client it wraps is
+/// configured with.
+///
+/// Note: on `wasm_client` the browser follows redirects before this middleware sees them, by its
+/// own rules, and does not report them in the `RedirectChain`. Fetch does not expose redirects
+/// to scripts, so this middleware cannot follow them there: with `FetchRedirect::Manual`
+/// browsers only return an opaque response in their place.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "h1_client")]
+/// # {
+/// use http_client::h1::H1Client;
+/// use http_client::middleware::{ClientWithMiddleware, Redirect, RedirectPolicy};
+///
+/// let redirect = Redirect::new(RedirectPolicy::custom(|attempt| {
+///     attempt.url().host_str() == Some("example.com")
+/// }));
+/// let client = ClientWithMiddleware::new(H1Client::new()).with(redirect);
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Redirect {
+    policy: RedirectPolicy,
+    extensions: ReplayExtensions,
+}
+
+impl Redirect {
+    /// Create a new instance following redirects according to `policy`.
+    pub fn new(policy: RedirectPolicy) -> Self {
+        Self {
+            policy,
+            extensions: ReplayExtensions::new(),
+        }
+    }
+
+    /// Copy request extensions of type `T` onto the requests sent for redirects.
+    pub fn replay_extension<T: Clone + Send + Sync + 'static>(mut self) -> Self {
+        self.extensions.add::<T>();
+        self
+    }
+}
+
+/// Marks requests whose redirects [`Redirect`] follows, so backends which could follow them
+/// return them instead.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FollowsRedirects;
+
+#[async_trait]
+impl Middleware for Redirect {
+    async fn handle(&self, mut req: Request, next: Next<'_>) -> Result<Response, Error> {
+        if let RedirectPolicy::None = self.policy {
+            return next.run(req).await;
+        }
+
+        let mut body = ReplayableBody::buffer(&mut req, MAX_REPLAY_BODY_SIZE).await?;
+        if let Some(body) = &body {
+            body.apply(&mut req);
+        }
+        let mut chain = vec![req.url().clone()];
+
+        loop {
+            let template = self.extensions.clone_request(&req);
+            req.ext_mut().insert(FollowsRedirects);
+            let res = next.run(req).await?;
+
+            let location = match redirect_location(&res, template.url()) {
+                Some(location) => location,
+                None => return Ok(finish(res, chain)),
+            };
+
+            let status = res.status();
+            let follow = match &self.policy {
+                RedirectPolicy::None => false,
+                RedirectPolicy::Limited(max) if chain.len() > *max => {
+                    let message = format!("too many redirects (more than {})", max);
+                    return Err(
+                        ClientError::new(ClientErrorKind::TooManyRedirects, message).into_error()
+                    );
+                }
+                RedirectPolicy::Limited(_) => true,
+                RedirectPolicy::Custom(f) => f(&RedirectAttempt {
+                    status,
+                    url: &location,
+                    previous: &chain,
+                }),
+            };
+            if !follow {
+                return Ok(finish(res, chain));
+            }
+
+            let method = template.method();
+            let switch_to_get = (status == StatusCode::SeeOther && method != Method::Head)
+                || (matches!(status, StatusCode::MovedPermanently | StatusCode::Found)
+                    && method == Method::Post);
+
+            req = template;
+            if switch_to_get {
+                req.set_method(Method::Get);
+                req.remove_header(CONTENT_TYPE);
+                req.remove_header(CONTENT_LENGTH);
+                body = Some(ReplayableBody::empty());
+            }
+            match &body {
+                Some(body) => body.apply(&mut req),
+                None => {
+                    log::debug!("not following redirect, the request body cannot be resent");
+                    return Ok(finish(res, chain));
+                }
+            }
+
+            if location.origin() != req.url().origin() {
+                req.remove_header(AUTHORIZATION);
+                req.remove_header(COOKIE);
+            }
+
+            log::trace!("following {} redirect to {}", status, location);
+            *req.url_mut() = location.clone();
+            chain.push(location);
+        }
+    }
+}
+
+fn finish(mut res: Response, chain: Vec<Url>) -> Response {
+    res.ext_mut().insert(RedirectChain { urls: chain });
+    res
+}
+
+/// The absolute URL a redirect response points to, if `res` is a redirect that can be followed.
+fn redirect_location(res: &Response, base: &Url) -> Option<Url> {
+    match res.status() {
+        StatusCode::MovedPermanently
+        | StatusCode::Found
+        | StatusCode::SeeOther
+        | StatusCode::TemporaryRedirect
+        | StatusCode::PermanentRedirect => {}
+        _ => return None,
+    }
+
+    let location = res.header(LOCATION)?.last();
+    let mut url = base.join(location.as_str()).ok()?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+
+    // A redirect without a fragment inherits the fragment of the original URL (RFC 7231, 7.1.2).
+    if url.fragment().is_none() {
+        url.set_fragment(base.fragment());
+    }
+    Some(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::ClientWithMiddleware;
+    use crate::{Config, HttpClient};
+    use std::sync::Mutex;
+
+    /// Redirects `/a` and `/b` to the next letter, with the status given in the query string.
+    #[derive(Debug)]
+    struct Redirector {
+        requests: Mutex<Vec<Request>>,
+        config: Config,
+    }
+
+    impl Redirector {
+        fn new() -> Self {
+            Self {
+                requests: Mutex::new(vec![]),
+                config: Config::new(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl HttpClient for Redirector {
+        async fn send(&self, mut req: Request) -> Result<Response, Error> {
+            // Record the request before reading the body, which adds a `Content-Type` header.
+            let mut recorded = req.clone();
+            if req.ext().get::<Tag>().is_some() {
+                recorded.ext_mut().insert(Tag);
+            }
+            let body = req.body_string().await?;
+            let status = req.url().query().unwrap_or("302").parse::<u16>()?;
+            let location = match req.url().path() {
+                "/a" => Some(format!("/b?{}", status)),
+                "/b" => Some("http://other.example/c".to_string()),
+                "/loop" => Some("/loop".to_string()),
+                _ => None,
+            };
+            self.requests.lock().unwrap().push(recorded);
+
+            let mut res = match location {
+                Some(location) => {
+                    let mut res = Response::new(status);
+                    res.insert_header(LOCATION, location);
+                    res
+                }
+                None => Response::new(StatusCode::Ok),
+            };
+            res.set_body(body);
+            Ok(res)
+        }
+
+        fn config(&self) -> &Config {
+            &self.config
+        }
+    }
+
+    /// A request extension.
+    #[derive(Clone, Debug)]
+    struct Tag;
+
+    fn request(method: Method, url: &str) -> Request {
+        let mut req = Request::new(method, Url::parse(url).unwrap());
+        req.insert_header(AUTHORIZATION, "Bearer secret");
+        req.set_body("hello");
+        req
+    }
+
+    #[async_std::test]
+    async fn follows_and_records_chain() -> http_types::Result<()> {
+        let redirect = Redirect::default().replay_extension::<Tag>();
+        let client = ClientWithMiddleware::new(Redirector::new()).with(redirect);
+
+        let mut req = request(Method::Put, "http://example.com/a?307");
+        req.ext_mut().insert(Tag);
+        let mut res = client.send(req).await?;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.body_string().await?, "hello");
+
+        let chain: &RedirectChain = res.ext().get().unwrap();
+        let urls: Vec<_> = chain.urls().iter().map(Url::as_str).collect();
+        assert_eq!(
+            urls,
+            [
+                "http://example.com/a?307",
+                "http://example.com/b?307",
+                "http://other.example/c"
+            ]
+        );
+
+        let requests = client.inner().requests.lock().unwrap();
+        assert!(requests.iter().all(|req| req.method() == Method::Put));
+        assert!(requests.iter().all(|req| req.ext().get::<Tag>().is_some()));
+        assert!(requests[1].header(AUTHORIZATION).is_some());
+        assert!(requests[2].header(AUTHORIZATION).is_none());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn see_other_switches_to_get() -> http_types::Result<()> {
+        let client = ClientWithMiddleware::new(Redirector::new()).with(Redirect::default());
+
+        let mut res = client
+            .send(request(Method::Post, "http://example.com/a?303"))
+            .await?;
+        assert_eq!(res.body_string().await?, "");
+
+        let requests = client.inner().requests.lock().unwrap();
+        assert_eq!(requests[0].method(), Method::Post);
+        assert_eq!(requests[1].method(), Method::Get);
+        assert!(requests[1].header(CONTENT_TYPE).is_none());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn policies_limit_redirects() -> http_types::Result<()> {
+        let client = ClientWithMiddleware::new(Redirector::new()).with(Redirect::new(
+            RedirectPolicy::custom(|attempt| attempt.url().host_str() == Some("example.com")),
+        ));
+        let res = client
+            .send(request(Method::Get, "http://example.com/a"))
+            .await?;
+        assert_eq!(res.status(), StatusCode::Found);
+        assert_eq!(res.ext().get::<RedirectChain>().unwrap().urls().len(), 2);
+
+        let client = ClientWithMiddleware::new(Redirector::new())
+            .with(Redirect::new(RedirectPolicy::Limited(3)));
+        let res = client
+            .send(request(Method::Get, "http://example.com/loop"))
+            .await;
+        let err = res.unwrap_err();
+        let kind = ClientError::of(&err).map(ClientError::kind);
+        assert_eq!(kind, Some(ClientErrorKind::TooManyRedirects));
+        assert_eq!(client.inner().requests.lock().unwrap().len(), 4);
+
+        let client =
+            ClientWithMiddleware::new(Redirector::new()).with(Redirect::new(RedirectPolicy::None));
+        let res = client
+            .send(request(Method::Get, "http://example.com/a"))
+            .await?;
+        assert_eq!(res.status(), StatusCode::Found);
+        Ok(())
+    }
+}
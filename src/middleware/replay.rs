@@ -0,0 +1,131 @@
+//! Buffering of request bodies and copying of request extensions, so requests can be sent more
+//! than once.
+
+use std::fmt::{self, Debug};
+use std::io;
+
+use futures_util::io::AsyncReadExt;
+use http_types::headers::CONTENT_TYPE;
+use http_types::mime::Mime;
+
+use crate::{Body, Request};
+
+/// A request body which was buffered so it can be sent more than once.
+pub(crate) struct ReplayableBody {
+    bytes: Vec<u8>,
+    mime: Mime,
+}
+
+impl ReplayableBody {
+    /// Buffer the body of `req` if it is no larger than `limit` bytes.
+    ///
+    /// If the body is too large, the request is left with an equivalent body and `None` is
+    /// returned.
+    pub(crate) async fn buffer(req: &mut Request, limit: usize) -> io::Result<Option<Self>> {
+        let body = replace_body(req, Body::empty());
+        if matches!(body.len(), Some(len) if len > limit) {
+            replace_body(req, body);
+            return Ok(None);
+        }
+
+        let mime = body.mime().clone();
+        let mut bytes = Vec::new();
+        let mut reader = body.take(limit as u64 + 1);
+        reader.read_to_end(&mut bytes).await?;
+
+        if bytes.len() > limit {
+            let rest = reader.into_inner();
+            let mut body = Body::from_bytes(bytes).chain(rest);
+            body.set_mime(mime);
+            replace_body(req, body);
+            return Ok(None);
+        }
+
+        Ok(Some(Self { bytes, mime }))
+    }
+
+    /// An empty body.
+    pub(crate) fn empty() -> Self {
+        Self {
+            bytes: Vec::new(),
+            mime: http_types::mime::BYTE_STREAM,
+        }
+    }
+
+    /// Set a copy of the buffered body as the body of `req`.
+    pub(crate) fn apply(&self, req: &mut Request) {
+        let mut body = Body::from_bytes(self.bytes.clone());
+        body.set_mime(self.mime.clone());
+        replace_body(req, body);
+    }
+}
+
+/// The request extensions which are copied onto every request a middleware sends again.
+///
+/// `Request::clone` drops extensions, which can't be cloned in general, so only the types of this
+/// crate read from requests and those added with [`add`](Self::add) are copied.
+#[derive(Clone)]
+pub(crate) struct ReplayExtensions {
+    copies: Vec<(&'static str, CopyExtension)>,
+}
+
+/// Copies an extension of one type from one request to another.
+type CopyExtension = fn(&Request, &mut Request);
+
+impl ReplayExtensions {
+    /// Copy the request extensions of this crate.
+    pub(crate) fn new() -> Self {
+        let mut this = Self { copies: Vec::new() };
+        #[cfg(all(feature = "h1_client", unix))]
+        this.add::<crate::h1::UnixSocket>();
+        this.add::<crate::FetchOptions>();
+        this
+    }
+
+    /// Also copy extensions of type `T`.
+    pub(crate) fn add<T: Clone + Send + Sync + 'static>(&mut self) {
+        let name = std::any::type_name::<T>();
+        if self.copies.iter().all(|(copied, _)| *copied != name) {
+            self.copies.push((name, copy::<T>));
+        }
+    }
+
+    /// Clone `req` without its body, along with the extensions to copy.
+    pub(crate) fn clone_request(&self, req: &Request) -> Request {
+        let mut clone = req.clone();
+        for (_, copy) in &self.copies {
+            copy(req, &mut clone);
+        }
+        clone
+    }
+}
+
+impl Default for ReplayExtensions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for ReplayExtensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.copies.iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+fn copy<T: Clone + Send + Sync + 'static>(from: &Request, to: &mut Request) {
+    if let Some(ext) = from.ext().get::<T>() {
+        to.ext_mut().insert(ext.clone());
+    }
+}
+
+/// Replace the body of `req` without adding a `Content-Type` header the request did not have.
+fn replace_body(req: &mut Request, body: Body) -> Body {
+    let had_content_type = req.header(CONTENT_TYPE).is_some();
+    let body = req.replace_body(body);
+    if !had_content_type {
+        req.remove_header(CONTENT_TYPE);
+    }
+    body
+}
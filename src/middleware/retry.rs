@@ -0,0 +1,468 @@
+//! Retry middleware.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::time::Duration;
+
+use futures_timer::Delay;
+use http_types::headers::RETRY_AFTER;
+use http_types::{Method, StatusCode};
+
+use super::replay::{ReplayExtensions, ReplayableBody};
+use super::{Middleware, Next};
+use crate::{async_trait, ClientError, ClientErrorKind, Error, Request, Response};
+
+/// Middleware which retries requests that failed with a transient error.
+///
+/// A request is retried when sending it failed with a transient I/O error (such as a refused or
+/// reset connection), or when the response status is one of
+/// [`retryable_statuses`](RetryPolicy::retryable_statuses). Between attempts the policy waits for
+/// an exponentially growing delay, optionally randomized, or for as long as the server asked for in
+/// a `Retry-After` header.
+///
+/// Request bodies are buffered so they can be sent again. Bodies larger than
+/// [`max_replay_body_size`](RetryPolicy::max_replay_body_size) are streamed as usual, and the
+/// request is not retried.
+///
+/// Retried requests carry the extensions of this crate, such as `h1::UnixSocket` and
+/// `FetchOptions`, and those added with [`replay_extension`](RetryPolicy::replay_extension).
+/// Other extensions are only sent with the first attempt, as they can't be cloned.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "h1_client")]
+/// # {
+/// use std::time::Duration;
+///
+/// use http_client::h1::H1Client;
+/// use http_client::middleware::{ClientWithMiddleware, RetryPolicy};
+///
+/// let retry = RetryPolicy::new()
+///     .set_max_attempts(5)
+///     .set_backoff_base(Duration::from_millis(250));
+/// let client = ClientWithMiddleware::new(H1Client::new()).with(retry);
+/// # }
+/// ```
+#[non_exhaustive]
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// The maximum number of times a request is sent, including the first attempt.
+    ///
+    /// Default: `3`.
+    pub max_attempts: u32,
+    /// The delay before the first retry. Each following retry waits twice as long as the last.
+    ///
+    /// Default: `Duration::from_millis(100)`.
+    pub backoff_base: Duration,
+    /// The longest delay between two attempts.
+    ///
+    /// A `Retry-After` header asking for a longer delay than this is not honored, and the
+    /// response is returned as-is.
+    ///
+    /// Default: `Duration::from_secs(10)`.
+    pub backoff_cap: Duration,
+    /// Randomize each delay between zero and the computed backoff ("full jitter").
+    ///
+    /// Default: `true`.
+    pub jitter: bool,
+    /// Response statuses which cause a request to be retried.
+    ///
+    /// Default: `502 Bad Gateway`, `503 Service Unavailable` and `504 Gateway Timeout`.
+    pub retryable_statuses: Vec<StatusCode>,
+    /// Only retry requests with idempotent methods (`GET`, `HEAD`, `OPTIONS`, `TRACE`, `PUT` and
+    /// `DELETE`).
+    ///
+    /// Default: `true`.
+    pub idempotent_only: bool,
+    /// Wait for the delay given by a `Retry-After` response header instead of the computed
+    /// backoff.
+    ///
+    /// Default: `true`.
+    pub respect_retry_after: bool,
+    /// The largest request body, in bytes, which is buffered so it can be replayed.
+    ///
+    /// Default: `65536`.
+    pub max_replay_body_size: usize,
+    extensions: ReplayExtensions,
+}
+
+impl RetryPolicy {
+    /// Construct a new retry policy with the default settings.
+    pub fn new() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff_base: Duration::from_millis(100),
+            backoff_cap: Duration::from_secs(10),
+            jitter: true,
+            retryable_statuses: vec![
+                StatusCode::BadGateway,
+                StatusCode::ServiceUnavailable,
+                StatusCode::GatewayTimeout,
+            ],
+            idempotent_only: true,
+            respect_retry_after: true,
+            max_replay_body_size: 64 * 1024,
+            extensions: ReplayExtensions::new(),
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RetryPolicy {
+    /// Set the maximum number of times a request is sent, including the first attempt.
+    pub fn set_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Set the delay before the first retry.
+    pub fn set_backoff_base(mut self, backoff_base: Duration) -> Self {
+        self.backoff_base = backoff_base;
+        self
+    }
+
+    /// Set the longest delay between two attempts.
+    pub fn set_backoff_cap(mut self, backoff_cap: Duration) -> Self {
+        self.backoff_cap = backoff_cap;
+        self
+    }
+
+    /// Set whether delays are randomized.
+    pub fn set_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Set the response statuses which cause a request to be retried.
+    pub fn set_retryable_statuses(
+        mut self,
+        statuses: impl IntoIterator<Item = StatusCode>,
+    ) -> Self {
+        self.retryable_statuses = statuses.into_iter().collect();
+        self
+    }
+
+    /// Set whether only requests with idempotent methods are retried.
+    pub fn set_idempotent_only(mut self, idempotent_only: bool) -> Self {
+        self.idempotent_only = idempotent_only;
+        self
+    }
+
+    /// Set whether `Retry-After` response headers are honored.
+    pub fn set_respect_retry_after(mut self, respect_retry_after: bool) -> Self {
+        self.respect_retry_after = respect_retry_after;
+        self
+    }
+
+    /// Set the largest request body, in bytes, which is buffered so it can be replayed.
+    pub fn set_max_replay_body_size(mut self, max_replay_body_size: usize) -> Self {
+        self.max_replay_body_size = max_replay_body_size;
+        self
+    }
+
+    /// Copy request extensions of type `T` onto every retried request.
+    pub fn replay_extension<T: Clone + Send + Sync + 'static>(mut self) -> Self {
+        self.extensions.add::<T>();
+        self
+    }
+
+    /// The backoff to wait for after the given (1-based) failed attempt.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt - 1).unwrap_or(u32::MAX);
+        let delay = self
+            .backoff_base
+            .checked_mul(factor)
+            .unwrap_or(self.backoff_cap)
+            .min(self.backoff_cap);
+
+        if self.jitter {
+            let nanos = delay.as_nanos().min(u64::MAX as u128) as u64;
+            Duration::from_nanos(random_u64() % nanos.saturating_add(1))
+        } else {
+            delay
+        }
+    }
+
+    /// The delay to wait for before retrying after `res`, or `None` if it should not be retried.
+    fn delay_for_response(&self, res: &Response, attempt: u32) -> Option<Duration> {
+        if !self.retryable_statuses.contains(&res.status()) {
+            return None;
+        }
+
+        match retry_after(res) {
+            Some(delay) if self.respect_retry_after => {
+                if delay > self.backoff_cap {
+                    None
+                } else {
+                    Some(delay)
+                }
+            }
+            _ => Some(self.backoff(attempt)),
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for RetryPolicy {
+    async fn handle(&self, mut req: Request, next: Next<'_>) -> Result<Response, Error> {
+        if self.max_attempts <= 1 || (self.idempotent_only && !is_idempotent(req.method())) {
+            return next.run(req).await;
+        }
+
+        let body = match ReplayableBody::buffer(&mut req, self.max_replay_body_size).await? {
+            Some(body) => body,
+            None => return next.run(req).await,
+        };
+
+        // `Request::clone` drops the body, so the first attempt sends the original.
+        let template = self.extensions.clone_request(&req);
+        body.apply(&mut req);
+
+        let mut attempt = 1;
+        loop {
+            let result = next.run(req).await;
+            if attempt >= self.max_attempts {
+                return result;
+            }
+
+            let delay = match &result {
+                Ok(res) => self.delay_for_response(res, attempt),
+                Err(err) if is_transient(err) => Some(self.backoff(attempt)),
+                Err(_) => None,
+            };
+            let delay = match delay {
+                Some(delay) => delay,
+                None => return result,
+            };
+
+            match &result {
+                Ok(res) => log::debug!(
+                    "attempt {} to {} returned {}, retrying in {:?}",
+                    attempt,
+                    template.url(),
+                    res.status(),
+                    delay
+                ),
+                Err(err) => log::debug!(
+                    "attempt {} to {} failed: {}, retrying in {:?}",
+                    attempt,
+                    template.url(),
+                    err,
+                    delay
+                ),
+            }
+            drop(result);
+
+            Delay::new(delay).await;
+            attempt += 1;
+
+            req = self.extensions.clone_request(&template);
+            body.apply(&mut req);
+        }
+    }
+}
+
+fn is_idempotent(method: Method) -> bool {
+    method.is_safe() || matches!(method, Method::Put | Method::Delete)
+}
+
+/// Whether `err` was caused by a failure that may not happen again.
+fn is_transient(err: &Error) -> bool {
+    let io_error = match ClientError::of(err) {
+        Some(err) => match err.kind() {
+            ClientErrorKind::Connect | ClientErrorKind::Timeout(_) => return true,
+            _ => err.get_ref().downcast_ref::<io::Error>(),
+        },
+        None => err.downcast_ref::<io::Error>(),
+    };
+    match io_error {
+        Some(err) => matches!(
+            err.kind(),
+            io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::NotConnected
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::UnexpectedEof
+        ),
+        None => false,
+    }
+}
+
+/// The delay requested by the `Retry-After` header of `res`, if any.
+fn retry_after(res: &Response) -> Option<Duration> {
+    let value = res.header(RETRY_AFTER)?.last();
+    if let Ok(secs) = value.as_str().trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    // The HTTP-date form needs the wall clock, which is not available on all wasm targets.
+    #[cfg(any(not(target_arch = "wasm32"), feature = "wasm_client"))]
+    {
+        let retry_after = http_types::other::RetryAfter::from_headers(res).ok()??;
+        Some(retry_after.duration_since(super::now()).unwrap_or_default())
+    }
+    #[cfg(all(target_arch = "wasm32", not(feature = "wasm_client")))]
+    None
+}
+
+/// A random number for jittering delays, without pulling in a random number generator.
+///
+/// Every `RandomState` is seeded differently, so hashing nothing with it yields a fresh number.
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::ClientWithMiddleware;
+    use crate::{Body, Config, FetchOptions, HttpClient};
+    use http_types::Url;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    /// Responds with the queued statuses in order, echoing the request body.
+    #[derive(Debug)]
+    struct Flaky {
+        statuses: Mutex<Vec<Result<StatusCode, io::ErrorKind>>>,
+        attempts: AtomicUsize,
+        tagged: AtomicUsize,
+        config: Config,
+    }
+
+    /// A request extension.
+    #[derive(Clone, Debug)]
+    struct Tag;
+
+    impl Flaky {
+        fn new(mut statuses: Vec<Result<StatusCode, io::ErrorKind>>) -> Self {
+            statuses.reverse();
+            Self {
+                statuses: Mutex::new(statuses),
+                attempts: AtomicUsize::new(0),
+                tagged: AtomicUsize::new(0),
+                config: Config::new(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl HttpClient for Flaky {
+        async fn send(&self, mut req: Request) -> Result<Response, Error> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            if req.ext().get::<Tag>().is_some() && req.ext().get::<FetchOptions>().is_some() {
+                self.tagged.fetch_add(1, Ordering::SeqCst);
+            }
+            let body = req.body_string().await?;
+            let status = self.statuses.lock().unwrap().pop().unwrap();
+            match status {
+                Ok(status) => {
+                    let mut res = Response::new(status);
+                    res.set_body(body);
+                    Ok(res)
+                }
+                Err(kind) => Err(io::Error::new(kind, "flaky").into()),
+            }
+        }
+
+        fn config(&self) -> &Config {
+            &self.config
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new()
+            .set_backoff_base(Duration::from_millis(1))
+            .set_jitter(false)
+    }
+
+    #[async_std::test]
+    async fn retries_transient_failures_and_replays_body() -> http_types::Result<()> {
+        let flaky = Flaky::new(vec![
+            Err(io::ErrorKind::ConnectionReset),
+            Ok(StatusCode::ServiceUnavailable),
+            Ok(StatusCode::Ok),
+        ]);
+        let client = ClientWithMiddleware::new(flaky).with(policy());
+
+        let mut req = Request::new(Method::Put, Url::parse("http://example.com/").unwrap());
+        req.set_body("hello");
+        let mut res = client.send(req).await?;
+
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.body_string().await?, "hello");
+        assert_eq!(client.inner().attempts.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn retries_keep_extensions() -> http_types::Result<()> {
+        let flaky = Flaky::new(vec![Ok(StatusCode::ServiceUnavailable), Ok(StatusCode::Ok)]);
+        let client = ClientWithMiddleware::new(flaky).with(policy().replay_extension::<Tag>());
+
+        let mut req = Request::new(Method::Get, Url::parse("http://example.com/").unwrap());
+        req.ext_mut().insert(Tag);
+        req.ext_mut().insert(FetchOptions::new());
+        client.send(req).await?;
+
+        assert_eq!(client.inner().attempts.load(Ordering::SeqCst), 2);
+        assert_eq!(client.inner().tagged.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn gives_up_after_max_attempts() -> http_types::Result<()> {
+        let flaky = Flaky::new(vec![Ok(StatusCode::BadGateway); 3]);
+        let client = ClientWithMiddleware::new(flaky).with(policy().set_max_attempts(2));
+
+        let req = Request::new(Method::Get, Url::parse("http://example.com/").unwrap());
+        let res = client.send(req).await?;
+
+        assert_eq!(res.status(), StatusCode::BadGateway);
+        assert_eq!(client.inner().attempts.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn skips_non_idempotent_and_oversized_requests() -> http_types::Result<()> {
+        let flaky = Flaky::new(vec![Ok(StatusCode::ServiceUnavailable); 2]);
+        let client = ClientWithMiddleware::new(flaky).with(policy().set_max_replay_body_size(4));
+
+        let req = Request::new(Method::Post, Url::parse("http://example.com/").unwrap());
+        client.send(req).await?;
+        assert_eq!(client.inner().attempts.load(Ordering::SeqCst), 1);
+
+        let mut req = Request::new(Method::Put, Url::parse("http://example.com/").unwrap());
+        req.set_body(Body::from_reader(
+            futures_util::io::Cursor::new("hello"),
+            None,
+        ));
+        let mut res = client.send(req).await?;
+        assert_eq!(res.body_string().await?, "hello");
+        assert_eq!(client.inner().attempts.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn backoff_is_exponential_and_capped() {
+        let policy = policy()
+            .set_backoff_base(Duration::from_millis(100))
+            .set_backoff_cap(Duration::from_millis(350));
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(350));
+        assert_eq!(policy.backoff(40), Duration::from_millis(350));
+
+        let policy = policy.set_jitter(true);
+        assert!(policy.backoff(3) <= Duration::from_millis(350));
+    }
+}
@@ -0,0 +1,205 @@
+use std::net::IpAddr;
+
+use http_types::url::{Host, Url};
+
+/// Hosts which requests are sent to directly, rather than through a proxy.
+///
+/// The list is parsed from the comma separated format of the `NO_PROXY` environment variable.
+/// Each entry is one of:
+///
+/// - `*`, which matches every host.
+/// - A domain name, such as `example.com`, which also matches its subdomains. A leading `.` or
+///   `*.` is ignored.
+/// - An IP address, such as `127.0.0.1` or `[::1]`.
+/// - A CIDR block, such as `10.0.0.0/8` or `fd00::/8`, which matches IP addresses in it.
+///
+/// Domain names and IP addresses may be followed by a `:port`, in which case the entry only
+/// matches URLs with that port.
+///
+/// # Examples
+///
+/// ```
+/// use http_client::NoProxy;
+/// use http_types::Url;
+///
+/// let no_proxy = NoProxy::new("localhost, .internal.example:8080, 10.0.0.0/8");
+/// assert!(no_proxy.matches(&Url::parse("http://localhost/")?));
+/// assert!(no_proxy.matches(&Url::parse("http://api.internal.example:8080/")?));
+/// assert!(!no_proxy.matches(&Url::parse("http://api.internal.example/")?));
+/// assert!(no_proxy.matches(&Url::parse("https://10.1.2.3/")?));
+/// # Ok::<(), http_types::url::ParseError>(())
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NoProxy {
+    rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Rule {
+    All,
+    Domain(String, Option<u16>),
+    Ip(IpAddr, Option<u16>),
+    Cidr(IpAddr, u8),
+}
+
+impl NoProxy {
+    /// Parse a comma separated list of hosts.
+    pub fn new(list: &str) -> Self {
+        let rules = list
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .filter_map(Rule::parse)
+            .collect();
+        Self { rules }
+    }
+
+    /// Whether requests to `url` should bypass the proxy.
+    pub fn matches(&self, url: &Url) -> bool {
+        let host = match url.host() {
+            Some(host) => host,
+            None => return false,
+        };
+        let port = url.port_or_known_default();
+        let ip = match host {
+            Host::Ipv4(ip) => Some(IpAddr::V4(ip)),
+            Host::Ipv6(ip) => Some(IpAddr::V6(ip)),
+            Host::Domain(_) => None,
+        };
+
+        self.rules.iter().any(|rule| match rule {
+            Rule::All => true,
+            Rule::Domain(domain, rule_port) => {
+                let host = match host {
+                    Host::Domain(host) => host.to_ascii_lowercase(),
+                    _ => return false,
+                };
+                let host = host.trim_end_matches('.');
+                let domain_matches = host == domain
+                    || (host.ends_with(domain.as_str())
+                        && host[..host.len() - domain.len()].ends_with('.'));
+                domain_matches && port_matches(*rule_port, port)
+            }
+            Rule::Ip(rule_ip, rule_port) => ip == Some(*rule_ip) && port_matches(*rule_port, port),
+            Rule::Cidr(network, prefix) => {
+                matches!(ip, Some(ip) if in_network(ip, *network, *prefix))
+            }
+        })
+    }
+}
+
+impl Rule {
+    fn parse(entry: &str) -> Option<Self> {
+        if entry == "*" {
+            return Some(Rule::All);
+        }
+
+        if let Some((network, prefix)) = entry.split_once('/') {
+            let network: IpAddr = network
+                .trim_matches(|c| c == '[' || c == ']')
+                .parse()
+                .ok()?;
+            let prefix: u8 = prefix.parse().ok()?;
+            let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+            if prefix > max_prefix {
+                log::debug!("ignoring invalid no_proxy entry {:?}", entry);
+                return None;
+            }
+            return Some(Rule::Cidr(network, prefix));
+        }
+
+        // A bare IPv6 address has colons, but no port.
+        if let Ok(ip) = entry.parse::<IpAddr>() {
+            return Some(Rule::Ip(ip, None));
+        }
+
+        let (host, port) = match entry.rsplit_once(':') {
+            Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => {
+                (host, Some(port.parse().ok()?))
+            }
+            _ => (entry, None),
+        };
+
+        if let Some(ip) = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+            return Some(Rule::Ip(ip.parse().ok()?, port));
+        }
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Some(Rule::Ip(ip, port));
+        }
+
+        let domain = host
+            .trim_start_matches("*.")
+            .trim_start_matches('.')
+            .trim_end_matches('.')
+            .to_ascii_lowercase();
+        if domain.is_empty() {
+            return None;
+        }
+        Some(Rule::Domain(domain, port))
+    }
+}
+
+/// Entries without a port match any port.
+fn port_matches(rule_port: Option<u16>, port: Option<u16>) -> bool {
+    match rule_port {
+        Some(rule_port) => Some(rule_port) == port,
+        None => true,
+    }
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(no_proxy: &str, url: &str) -> bool {
+        NoProxy::new(no_proxy).matches(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn matches_domains_and_ports() {
+        assert!(matches("example.com", "http://example.com/"));
+        assert!(matches("example.com", "http://www.example.com/"));
+        assert!(matches(".example.com", "http://www.example.com/"));
+        assert!(matches("*.example.com", "http://example.com./"));
+        assert!(!matches("example.com", "http://notexample.com/"));
+        assert!(!matches("www.example.com", "http://example.com/"));
+
+        assert!(matches("example.com:8080", "http://example.com:8080/"));
+        assert!(!matches("example.com:8080", "http://example.com/"));
+        assert!(matches("example.com:443", "https://example.com/"));
+
+        assert!(matches("foo, *", "http://anything/"));
+        assert!(!matches("", "http://example.com/"));
+    }
+
+    #[test]
+    fn matches_ips_and_cidrs() {
+        assert!(matches("127.0.0.1", "http://127.0.0.1:3000/"));
+        assert!(matches("127.0.0.1:3000", "http://127.0.0.1:3000/"));
+        assert!(!matches("127.0.0.1:3000", "http://127.0.0.1:4000/"));
+        assert!(matches("::1", "http://[::1]/"));
+        assert!(matches("[::1]:80", "http://[::1]/"));
+
+        assert!(matches("10.0.0.0/8", "http://10.20.30.40/"));
+        assert!(!matches("10.0.0.0/8", "http://11.0.0.1/"));
+        assert!(matches("0.0.0.0/0", "http://1.2.3.4/"));
+        assert!(matches("fd00::/8", "http://[fd12::1]/"));
+        assert!(!matches("fd00::/8", "http://[fe80::1]/"));
+        assert!(!matches("10.0.0.0/8", "http://ten.example/"));
+        assert!(!matches("10.0.0.0/33", "http://10.0.0.1/"));
+    }
+}
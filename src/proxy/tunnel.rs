@@ -0,0 +1,262 @@
+//! Tunnels through HTTP and SOCKS5 proxies, shared by the native backends.
+
+use std::io;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use futures_util::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use http_types::headers::PROXY_AUTHORIZATION;
+use http_types::url::{Host, Url};
+
+use super::Proxy;
+use crate::resolve::lookup;
+use crate::{ClientError, ClientErrorKind, Resolve};
+
+/// The largest response head accepted from a proxy in reply to `CONNECT`.
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+
+/// A tunnel through a proxy to a target host, established on a new connection to the proxy before
+/// it is used: with `CONNECT` for HTTP proxies, or with the SOCKS5 handshake.
+#[derive(Clone, Debug)]
+pub(crate) struct Tunnel {
+    proxy: Proxy,
+    host: Host<String>,
+    port: u16,
+    resolver: Arc<dyn Resolve>,
+}
+
+impl Tunnel {
+    /// Create a tunnel to the host of `url`, using `resolver` when the proxy must be sent an IP
+    /// address.
+    pub(crate) fn new(proxy: Proxy, url: &Url, resolver: Arc<dyn Resolve>) -> Option<Self> {
+        Some(Self {
+            proxy,
+            host: url.host()?.to_owned(),
+            port: url.port_or_known_default()?,
+            resolver,
+        })
+    }
+
+    /// Ask the proxy at the other end of `stream` to open the tunnel.
+    pub(crate) async fn establish<S>(&self, stream: &mut S) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match self.proxy.url().scheme() {
+            "socks5" => self.socks5(stream, false).await,
+            "socks5h" => self.socks5(stream, true).await,
+            _ => self.connect(stream).await,
+        }
+    }
+
+    // `io::Error::other` needs a newer Rust than this crate supports.
+    #[allow(clippy::io_other_error)]
+    async fn connect<S>(&self, stream: &mut S) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let target = format!("{}:{}", self.host, self.port);
+        let mut head = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+        if let Some(authorization) = self.proxy.authorization() {
+            head.push_str(&format!("{}: {}\r\n", PROXY_AUTHORIZATION, authorization));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes()).await?;
+        stream.flush().await?;
+
+        // Read one byte at a time, so nothing the target sends through the tunnel is consumed.
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() >= MAX_HEAD_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "proxy response head is too long",
+                ));
+            }
+            let mut byte = [0; 1];
+            if stream.read(&mut byte).await? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "proxy closed the connection before responding to CONNECT",
+                ));
+            }
+            head.push(byte[0]);
+        }
+
+        let head = String::from_utf8_lossy(&head);
+        // UNWRAP: `head` ends with a line break, so it has a first line.
+        let status_line = head.lines().next().unwrap();
+        let mut parts = status_line.splitn(3, ' ');
+        let status = match (parts.next(), parts.next()) {
+            (Some(version), Some(status)) if version.starts_with("HTTP/1.") => status,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid proxy response: {:?}", status_line),
+                ))
+            }
+        };
+
+        if status.starts_with('2') {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "proxy refused to open a tunnel to {}: {}",
+                    target,
+                    status_line.split_once(' ').map_or("", |(_, rest)| rest)
+                ),
+            ))
+        }
+    }
+
+    /// Perform the SOCKS5 handshake (RFC 1928), with username/password authentication (RFC 1929)
+    /// when the proxy URL has credentials.
+    ///
+    /// With `remote_dns`, domain names are sent to the proxy to resolve. Otherwise they are
+    /// resolved locally, and the proxy is sent an IP address.
+    async fn socks5<S>(&self, stream: &mut S, remote_dns: bool) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let credentials = self.proxy.credentials();
+        let method = if credentials.is_some() {
+            SOCKS5_USERNAME_PASSWORD
+        } else {
+            SOCKS5_NO_AUTHENTICATION
+        };
+        stream.write_all(&[SOCKS5_VERSION, 1, method]).await?;
+        stream.flush().await?;
+
+        let mut reply = [0; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != SOCKS5_VERSION {
+            return Err(socks5_error("invalid SOCKS5 proxy response"));
+        }
+        if reply[1] != method {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "SOCKS5 proxy rejected the authentication method",
+            ));
+        }
+
+        if let Some((username, password)) = credentials {
+            if username.len() > 255 || password.len() > 255 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "SOCKS5 username and password must be at most 255 bytes long",
+                ));
+            }
+            let mut request = vec![1, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            stream.write_all(&request).await?;
+            stream.flush().await?;
+
+            let mut reply = [0; 2];
+            stream.read_exact(&mut reply).await?;
+            if reply[1] != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "SOCKS5 proxy rejected the username or password",
+                ));
+            }
+        }
+
+        let mut request = vec![SOCKS5_VERSION, SOCKS5_CONNECT, 0];
+        let ip = match &self.host {
+            Host::Ipv4(ip) => Some(IpAddr::V4(*ip)),
+            Host::Ipv6(ip) => Some(IpAddr::V6(*ip)),
+            Host::Domain(domain) if remote_dns => {
+                if domain.len() > 255 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "SOCKS5 domain names must be at most 255 bytes long",
+                    ));
+                }
+                request.push(SOCKS5_DOMAIN);
+                request.push(domain.len() as u8);
+                request.extend_from_slice(domain.as_bytes());
+                None
+            }
+            Host::Domain(domain) => {
+                let addrs = lookup(&*self.resolver, domain, self.port)
+                    .await
+                    .map_err(|e| ClientError::from_io(e, ClientErrorKind::Dns))?;
+                Some(addrs[0].ip())
+            }
+        };
+        match ip {
+            Some(IpAddr::V4(ip)) => {
+                request.push(SOCKS5_IPV4);
+                request.extend_from_slice(&ip.octets());
+            }
+            Some(IpAddr::V6(ip)) => {
+                request.push(SOCKS5_IPV6);
+                request.extend_from_slice(&ip.octets());
+            }
+            None => {}
+        }
+        request.extend_from_slice(&self.port.to_be_bytes());
+        stream.write_all(&request).await?;
+        stream.flush().await?;
+
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != SOCKS5_VERSION {
+            return Err(socks5_error("invalid SOCKS5 proxy response"));
+        }
+        if reply[1] != 0 {
+            let (kind, reason) = match reply[1] {
+                2 => (
+                    io::ErrorKind::PermissionDenied,
+                    "connection not allowed by ruleset",
+                ),
+                3 => (io::ErrorKind::Other, "network unreachable"),
+                4 => (io::ErrorKind::Other, "host unreachable"),
+                5 => (io::ErrorKind::ConnectionRefused, "connection refused"),
+                6 => (io::ErrorKind::TimedOut, "TTL expired"),
+                7 => (io::ErrorKind::Other, "command not supported"),
+                8 => (io::ErrorKind::Other, "address type not supported"),
+                _ => (io::ErrorKind::Other, "general failure"),
+            };
+            return Err(io::Error::new(
+                kind,
+                format!(
+                    "SOCKS5 proxy could not connect to {}:{}: {}",
+                    self.host, self.port, reason
+                ),
+            ));
+        }
+
+        // Skip the address the proxy bound to, which is of no use to us.
+        let len = match reply[3] {
+            SOCKS5_IPV4 => 4,
+            SOCKS5_IPV6 => 16,
+            SOCKS5_DOMAIN => {
+                let mut len = [0; 1];
+                stream.read_exact(&mut len).await?;
+                len[0] as usize
+            }
+            _ => return Err(socks5_error("invalid SOCKS5 proxy response")),
+        };
+        let mut bound = vec![0; len + 2];
+        stream.read_exact(&mut bound).await?;
+
+        Ok(())
+    }
+}
+
+const SOCKS5_VERSION: u8 = 5;
+const SOCKS5_NO_AUTHENTICATION: u8 = 0;
+const SOCKS5_USERNAME_PASSWORD: u8 = 2;
+const SOCKS5_CONNECT: u8 = 1;
+const SOCKS5_IPV4: u8 = 1;
+const SOCKS5_DOMAIN: u8 = 3;
+const SOCKS5_IPV6: u8 = 4;
+
+fn socks5_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
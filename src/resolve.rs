@@ -0,0 +1,311 @@
+//! Resolving hostnames to the addresses connections are opened to.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::async_trait;
+#[cfg(any(feature = "h1_client", feature = "hyper_client"))]
+use crate::Config;
+
+/// Resolves hostnames to the addresses connections are opened to.
+///
+/// Set one with `Config::resolver`. IP addresses in URLs are used as they are, without being
+/// passed to the resolver.
+///
+/// # Examples
+///
+/// ```
+/// use std::io;
+/// use std::net::{Ipv4Addr, SocketAddr};
+///
+/// use http_client::{async_trait, Resolve};
+///
+/// /// Resolves every hostname to localhost.
+/// #[derive(Debug)]
+/// struct Localhost;
+///
+/// #[async_trait]
+/// impl Resolve for Localhost {
+///     async fn resolve(&self, _host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+///         Ok(vec![SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port)])
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait Resolve: Debug + Send + Sync + 'static {
+    /// Resolve `host` to the addresses of `port` on it.
+    async fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>>;
+}
+
+/// The resolver of the operating system, run without blocking the async executor.
+///
+/// Used when `Config::resolver` is not set.
+#[cfg_attr(
+    feature = "docs",
+    doc(cfg(any(feature = "h1_client", feature = "hyper_client")))
+)]
+#[cfg(any(feature = "h1_client", feature = "hyper_client"))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+#[cfg(any(feature = "h1_client", feature = "hyper_client"))]
+#[async_trait]
+impl Resolve for SystemResolver {
+    async fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "h1_client")] {
+                use async_std::net::ToSocketAddrs;
+
+                Ok((host, port).to_socket_addrs().await?.collect())
+            } else {
+                use std::str::FromStr;
+
+                use hyper::client::connect::dns::{GaiResolver, Name};
+                use hyper::service::Service;
+
+                let name = Name::from_str(host)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let ips = GaiResolver::new().call(name).await?;
+                Ok(ips.map(|ip| SocketAddr::new(ip, port)).collect())
+            }
+        }
+    }
+}
+
+/// Caches the addresses another resolver returns for a fixed `lifetime`, whatever their DNS TTL.
+///
+/// The system resolver does not report the TTLs of the records it resolves, so every address is
+/// reused for `lifetime` after it was resolved, even if its record expires sooner. Keep it short
+/// for hosts whose addresses change often. Failed lookups are not cached.
+///
+/// # Examples
+///
+/// ```
+/// use std::net::Ipv4Addr;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// use http_client::{Config, FixedLifetimeResolver, StaticResolver};
+///
+/// let inner = StaticResolver::new().with_host("example.com", vec![Ipv4Addr::LOCALHOST.into()]);
+/// let resolver = FixedLifetimeResolver::new(inner, Duration::from_secs(60));
+/// let config = Config::new().set_resolver(Some(Arc::new(resolver)));
+/// ```
+#[derive(Debug)]
+pub struct FixedLifetimeResolver<R> {
+    inner: R,
+    lifetime: Duration,
+    cache: Mutex<Cache>,
+}
+
+/// Addresses by hostname and port, with when they expire.
+type Cache = HashMap<(String, u16), (Instant, Vec<SocketAddr>)>;
+
+impl<R: Resolve> FixedLifetimeResolver<R> {
+    /// Cache the addresses `inner` returns for `lifetime` each.
+    pub fn new(inner: R, lifetime: Duration) -> Self {
+        Self {
+            inner,
+            lifetime,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Forget every cached address.
+    pub fn clear(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+}
+
+#[async_trait]
+impl<R: Resolve> Resolve for FixedLifetimeResolver<R> {
+    async fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let key = (host.to_ascii_lowercase(), port);
+        {
+            let mut cache = self.cache.lock().unwrap();
+            match cache.get(&key) {
+                Some((expires, addrs)) if *expires > Instant::now() => return Ok(addrs.clone()),
+                Some(_) => {
+                    cache.remove(&key);
+                }
+                None => {}
+            }
+        }
+
+        let addrs = self.inner.resolve(host, port).await?;
+        let expires = Instant::now() + self.lifetime;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(key, (expires, addrs.clone()));
+        Ok(addrs)
+    }
+}
+
+/// Resolves hostnames to fixed IP addresses, like `/etc/hosts`, and any others with a fallback.
+///
+/// # Examples
+///
+/// ```
+/// use std::net::Ipv4Addr;
+/// use std::sync::Arc;
+///
+/// use http_client::{Config, StaticResolver};
+///
+/// let resolver = StaticResolver::new().with_host("example.com", vec![Ipv4Addr::LOCALHOST.into()]);
+/// let config = Config::new().set_resolver(Some(Arc::new(resolver)));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct StaticResolver {
+    hosts: HashMap<String, Vec<IpAddr>>,
+    fallback: Option<Arc<dyn Resolve>>,
+}
+
+impl StaticResolver {
+    /// Create a new instance, without any hosts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve `host` to `ips`.
+    pub fn with_host(mut self, host: &str, ips: impl IntoIterator<Item = IpAddr>) -> Self {
+        self.hosts
+            .insert(normalize(host), ips.into_iter().collect());
+        self
+    }
+
+    /// Set the resolver for other hostnames. Without one, resolving them fails.
+    pub fn set_fallback(mut self, fallback: Option<Arc<dyn Resolve>>) -> Self {
+        self.fallback = fallback;
+        self
+    }
+}
+
+#[async_trait]
+impl Resolve for StaticResolver {
+    async fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        if let Some(ips) = self.hosts.get(&normalize(host)) {
+            return Ok(ips.iter().map(|ip| SocketAddr::new(*ip, port)).collect());
+        }
+        match &self.fallback {
+            Some(fallback) => fallback.resolve(host, port).await,
+            None => Err(not_found(host)),
+        }
+    }
+}
+
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn not_found(host: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("could not resolve {}", host),
+    )
+}
+
+/// The resolver set in `config`, or the system resolver.
+#[cfg(any(feature = "h1_client", feature = "hyper_client"))]
+pub(crate) fn resolver(config: &Config) -> Arc<dyn Resolve> {
+    match &config.resolver {
+        Some(resolver) => resolver.clone(),
+        None => Arc::new(SystemResolver),
+    }
+}
+
+/// Resolve `host` with `resolver`, unless it is an IP address. Never returns no addresses.
+#[cfg(any(feature = "h1_client", feature = "hyper_client"))]
+pub(crate) async fn lookup(
+    resolver: &dyn Resolve,
+    host: &str,
+    port: u16,
+) -> io::Result<Vec<SocketAddr>> {
+    let ip = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = ip.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+
+    let addrs = resolver.resolve(host, port).await?;
+    if addrs.is_empty() {
+        return Err(not_found(host));
+    }
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Resolves every hostname to a new address, counting up from `127.0.0.1`.
+    #[derive(Debug, Default)]
+    struct Counter(AtomicUsize);
+
+    #[async_trait]
+    impl Resolve for Counter {
+        async fn resolve(&self, _host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+            let n = self.0.fetch_add(1, Ordering::SeqCst) as u8;
+            let ip = Ipv4Addr::new(127, 0, 0, 1 + n);
+            Ok(vec![SocketAddr::new(ip.into(), port)])
+        }
+    }
+
+    fn addr(ip: [u8; 4], port: u16) -> SocketAddr {
+        SocketAddr::new(Ipv4Addr::from(ip).into(), port)
+    }
+
+    #[async_std::test]
+    async fn caches_for_lifetime() -> io::Result<()> {
+        let resolver = FixedLifetimeResolver::new(Counter::default(), Duration::from_millis(100));
+        assert_eq!(
+            resolver.resolve("a.test", 80).await?,
+            [addr([127, 0, 0, 1], 80)]
+        );
+        assert_eq!(
+            resolver.resolve("A.test", 80).await?,
+            [addr([127, 0, 0, 1], 80)]
+        );
+        assert_eq!(
+            resolver.resolve("b.test", 80).await?,
+            [addr([127, 0, 0, 2], 80)]
+        );
+
+        async_std::task::sleep(Duration::from_millis(150)).await;
+        assert_eq!(
+            resolver.resolve("a.test", 80).await?,
+            [addr([127, 0, 0, 3], 80)]
+        );
+
+        resolver.clear();
+        assert_eq!(
+            resolver.resolve("a.test", 80).await?,
+            [addr([127, 0, 0, 4], 80)]
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn overrides_hosts() -> io::Result<()> {
+        let pinned = [Ipv4Addr::new(10, 0, 0, 1).into()];
+        let resolver = StaticResolver::new().with_host("Pinned.test.", pinned);
+        assert_eq!(
+            resolver.resolve("pinned.test", 443).await?,
+            [addr([10, 0, 0, 1], 443)]
+        );
+        let err = resolver.resolve("other.test", 443).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        let resolver = resolver.set_fallback(Some(Arc::new(Counter::default())));
+        assert_eq!(
+            resolver.resolve("other.test", 443).await?,
+            [addr([127, 0, 0, 1], 443)]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+//! Local servers for testing requests: origins, with and without TLS and keep-alive, and
+//! proxies.
+
+#[cfg(feature = "h1_client")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_std::net::{SocketAddr, TcpListener, TcpStream};
+use async_std::prelude::*;
+use async_std::task;
+use http_types::url::{Position, Url};
+use tide_rustls::async_tls::TlsAcceptor;
+use tide_rustls::rustls::internal::pemfile;
+use tide_rustls::rustls::sign::{self, CertifiedKey};
+use tide_rustls::rustls::{
+    AllowAnyAuthenticatedClient, ClientHello, NoClientAuth, ResolvesServerCert, RootCertStore,
+    ServerConfig,
+};
+
+/// The CA which issued the certificate of `spawn_tls_origin`.
+pub(crate) const CA: &[u8] = include_bytes!("../tests/fixtures/ca.pem");
+const CERT: &[u8] = include_bytes!("../tests/fixtures/a-b.test.pem");
+const KEY: &[u8] = include_bytes!("../tests/fixtures/a-b.test.key");
+/// The pin of the key of `spawn_tls_origin`.
+pub(crate) const SERVER_PIN: &str = "sha256/LjBBLTGhVe7XmvZZ72oP4wBKmYsDxZCMMXYS788wIWQ=";
+/// The pin of a key `spawn_tls_origin` does not have.
+pub(crate) const OTHER_PIN: &str = "sha256/UYCXKB4cB/kDG20azjH1TwXOeu+v3IQJsqH3iFn2WXE=";
+const CLIENT_CA: &[u8] = include_bytes!("../tests/fixtures/client-ca.pem");
+
+/// Read a request or response head, one byte at a time.
+pub(crate) async fn read_head<S: async_std::io::Read + Unpin>(stream: &mut S) -> String {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0; 1];
+        if stream.read(&mut byte).await.unwrap() == 0 {
+            break;
+        }
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+/// A server which responds to a single request on each connection with its request line.
+pub(crate) async fn spawn_origin() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    task::spawn(async move {
+        while let Some(Ok(mut stream)) = listener.incoming().next().await {
+            task::spawn(async move {
+                let head = read_head(&mut stream).await;
+                let line = head.lines().next().unwrap_or_default();
+                let res = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
+                    line.len(),
+                    line
+                );
+                stream.write_all(res.as_bytes()).await.unwrap();
+            });
+        }
+    });
+    addr
+}
+
+/// Counts of the connections a test server has accepted, and of those which are still open.
+#[cfg(feature = "h1_client")]
+#[derive(Debug, Default)]
+pub(crate) struct Connections {
+    pub(crate) accepted: AtomicUsize,
+    pub(crate) open: AtomicUsize,
+}
+
+/// A server which responds to each request on a connection with its request line, until the
+/// client closes the connection.
+#[cfg(feature = "h1_client")]
+pub(crate) async fn spawn_keep_alive() -> (SocketAddr, Arc<Connections>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connections = Arc::new(Connections::default());
+    let counted = connections.clone();
+    task::spawn(async move {
+        while let Some(Ok(mut stream)) = listener.incoming().next().await {
+            let connections = connections.clone();
+            connections.accepted.fetch_add(1, Ordering::SeqCst);
+            connections.open.fetch_add(1, Ordering::SeqCst);
+            task::spawn(async move {
+                loop {
+                    let head = read_head(&mut stream).await;
+                    let line = match head.lines().next() {
+                        Some(line) => line,
+                        None => break,
+                    };
+                    let res = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
+                        line.len(),
+                        line
+                    );
+                    stream.write_all(res.as_bytes()).await.unwrap();
+                }
+                connections.open.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+    (addr, counted)
+}
+
+/// Serves a certificate for both `a.test` and `b.test`, recording the server name each
+/// handshake asks for.
+struct RecordServerNames {
+    key: CertifiedKey,
+    names: Arc<Mutex<Vec<String>>>,
+}
+
+impl ResolvesServerCert for RecordServerNames {
+    fn resolve(&self, hello: ClientHello<'_>) -> Option<CertifiedKey> {
+        let name: &str = hello.server_name()?.into();
+        self.names.lock().unwrap().push(name.to_string());
+        Some(self.key.clone())
+    }
+}
+
+/// A TLS server for `a.test` and `b.test` which responds to every request with `ok`, and records
+/// the server names clients ask for.
+///
+/// With `client_auth`, clients must present a certificate issued by the client CA.
+pub(crate) async fn spawn_tls_origin(client_auth: bool) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    let certs = pemfile::certs(&mut &CERT[..]).unwrap();
+    let key = pemfile::pkcs8_private_keys(&mut &KEY[..])
+        .unwrap()
+        .remove(0);
+    let key = sign::any_supported_type(&key).unwrap();
+    let names = Arc::new(Mutex::new(Vec::new()));
+    let mut config = if client_auth {
+        let mut roots = RootCertStore::empty();
+        roots.add_pem_file(&mut &CLIENT_CA[..]).unwrap();
+        ServerConfig::new(AllowAnyAuthenticatedClient::new(roots))
+    } else {
+        ServerConfig::new(NoClientAuth::new())
+    };
+    config.cert_resolver = Arc::new(RecordServerNames {
+        key: CertifiedKey::new(certs, Arc::new(key)),
+        names: names.clone(),
+    });
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    task::spawn(async move {
+        while let Some(Ok(stream)) = listener.incoming().next().await {
+            let acceptor = acceptor.clone();
+            task::spawn(async move {
+                // Clients without a certificate fail the handshake.
+                let mut stream = match acceptor.accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+                while !read_head(&mut stream).await.is_empty() {
+                    let res = b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok";
+                    stream.write_all(res).await.unwrap();
+                }
+            });
+        }
+    });
+    (addr, names)
+}
+
+/// A server which accepts connections, but never says anything on them.
+pub(crate) async fn spawn_silent() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    task::spawn(async move {
+        let mut streams = vec![];
+        while let Some(Ok(stream)) = listener.incoming().next().await {
+            streams.push(stream);
+        }
+    });
+    addr
+}
+
+/// A proxy which records the request heads it receives, and forwards them to their target.
+pub(crate) async fn spawn_proxy() -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let heads = Arc::new(Mutex::new(Vec::new()));
+    let recorded = heads.clone();
+    task::spawn(async move {
+        while let Some(Ok(mut client)) = listener.incoming().next().await {
+            let heads = heads.clone();
+            task::spawn(async move {
+                let head = read_head(&mut client).await;
+                heads.lock().unwrap().push(head.clone());
+                let mut parts = head.split(' ');
+                let method = parts.next().unwrap();
+                let target = parts.next().unwrap();
+
+                let upstream = if method == "CONNECT" {
+                    let upstream = TcpStream::connect(target).await.unwrap();
+                    client
+                        .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+                        .await
+                        .unwrap();
+                    upstream
+                } else {
+                    let url = Url::parse(target).unwrap();
+                    let mut upstream = TcpStream::connect(&*url.socket_addrs(|| None).unwrap())
+                        .await
+                        .unwrap();
+                    let head =
+                        head.replacen(target, &url[Position::BeforePath..Position::AfterQuery], 1);
+                    upstream.write_all(head.as_bytes()).await.unwrap();
+                    upstream
+                };
+
+                relay(client, upstream).await;
+            });
+        }
+    });
+    (addr, recorded)
+}
+
+/// A SOCKS5 proxy which requires the credentials `user:pass`, records the target host it is
+/// sent, and forwards the connection to it.
+pub(crate) async fn spawn_socks5() -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let hosts = Arc::new(Mutex::new(Vec::new()));
+    let recorded = hosts.clone();
+    task::spawn(async move {
+        while let Some(Ok(mut client)) = listener.incoming().next().await {
+            let hosts = hosts.clone();
+            task::spawn(async move {
+                let mut greeting = [0; 2];
+                client.read_exact(&mut greeting).await.unwrap();
+                let mut methods = vec![0; greeting[1] as usize];
+                client.read_exact(&mut methods).await.unwrap();
+                assert!(methods.contains(&2));
+                client.write_all(&[5, 2]).await.unwrap();
+
+                let mut credentials = [0; 2];
+                client.read_exact(&mut credentials).await.unwrap();
+                let mut username = vec![0; credentials[1] as usize];
+                client.read_exact(&mut username).await.unwrap();
+                client.read_exact(&mut credentials[..1]).await.unwrap();
+                let mut password = vec![0; credentials[0] as usize];
+                client.read_exact(&mut password).await.unwrap();
+                if (&username[..], &password[..]) != (b"user", b"pass") {
+                    client.write_all(&[1, 1]).await.unwrap();
+                    return;
+                }
+                client.write_all(&[1, 0]).await.unwrap();
+
+                let mut request = [0; 4];
+                client.read_exact(&mut request).await.unwrap();
+                let host = match request[3] {
+                    1 => {
+                        let mut ip = [0; 4];
+                        client.read_exact(&mut ip).await.unwrap();
+                        std::net::Ipv4Addr::from(ip).to_string()
+                    }
+                    3 => {
+                        let mut len = [0; 1];
+                        client.read_exact(&mut len).await.unwrap();
+                        let mut domain = vec![0; len[0] as usize];
+                        client.read_exact(&mut domain).await.unwrap();
+                        String::from_utf8(domain).unwrap()
+                    }
+                    _ => unimplemented!(),
+                };
+                let mut port = [0; 2];
+                client.read_exact(&mut port).await.unwrap();
+                hosts.lock().unwrap().push(host.clone());
+
+                let upstream = TcpStream::connect((host.as_str(), u16::from_be_bytes(port)))
+                    .await
+                    .unwrap();
+                client
+                    .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+                    .await
+                    .unwrap();
+                relay(client, upstream).await;
+            });
+        }
+    });
+    (addr, recorded)
+}
+
+pub(crate) async fn relay(mut client: TcpStream, mut upstream: TcpStream) {
+    let (mut client_reader, mut upstream_reader) = (client.clone(), upstream.clone());
+    let _ = async_std::io::copy(&mut client_reader, &mut upstream)
+        .race(async_std::io::copy(&mut upstream_reader, &mut client))
+        .await;
+}
@@ -0,0 +1,221 @@
+//! Timeouts shared by the backends.
+//!
+//! Timers come from `futures-timer`, so they work the same on every runtime.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_timer::Delay;
+use futures_util::future::{self, Either};
+use futures_util::io::{AsyncBufRead, AsyncRead};
+
+use crate::error::{ClientError, TimeoutError, TimeoutKind};
+use crate::{Body, Config, Error, Response};
+
+/// Run `fut`, failing with a `kind` timeout if it takes longer than `duration`.
+pub(crate) async fn timeout<F: Future>(
+    kind: TimeoutKind,
+    duration: Option<Duration>,
+    fut: F,
+) -> io::Result<F::Output> {
+    let duration = match duration {
+        Some(duration) => duration,
+        None => return Ok(fut.await),
+    };
+
+    futures_util::pin_mut!(fut);
+    match future::select(fut, Delay::new(duration)).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => Err(TimeoutError::new(kind, duration).into()),
+    }
+}
+
+/// Run `send` within `Config::total_timeout`, and apply the rest of it, along with
+/// `Config::read_idle_timeout`, to reading the response body.
+pub(crate) async fn send<F>(config: &Config, send: F) -> Result<Response, Error>
+where
+    F: Future<Output = Result<Response, Error>>,
+{
+    let mut deadline = config
+        .total_timeout
+        .map(|duration| (duration, Delay::new(duration)));
+
+    let mut res = match &mut deadline {
+        Some((duration, delay)) => {
+            futures_util::pin_mut!(send);
+            match future::select(send, delay).await {
+                Either::Left((res, _)) => res?,
+                Either::Right(_) => {
+                    let err = TimeoutError::new(TimeoutKind::Total, *duration);
+                    return Err(ClientError::from(err).into_error());
+                }
+            }
+        }
+        None => send.await?,
+    };
+
+    if deadline.is_some() || config.read_idle_timeout.is_some() {
+        let body = res.take_body();
+        let len = body.len();
+        let mime = body.mime().clone();
+        let idle = config
+            .read_idle_timeout
+            .map(|duration| (duration, Delay::new(duration)));
+
+        let mut body = Body::from_reader(
+            TimeoutBody {
+                body,
+                idle,
+                deadline,
+            },
+            len,
+        );
+        body.set_mime(mime);
+        res.set_body(body);
+    }
+
+    Ok(res)
+}
+
+/// A response body which fails when its timers fire while it waits for data.
+struct TimeoutBody {
+    body: Body,
+    /// Reset whenever the body makes progress.
+    idle: Option<(Duration, Delay)>,
+    deadline: Option<(Duration, Delay)>,
+}
+
+impl AsyncRead for TimeoutBody {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.body).poll_read(cx, buf) {
+            Poll::Ready(res) => {
+                reset(&mut this.idle);
+                Poll::Ready(res)
+            }
+            Poll::Pending => {
+                poll_timers(&mut this.idle, &mut this.deadline, cx)?;
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl AsyncBufRead for TimeoutBody {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.body).poll_fill_buf(cx) {
+            Poll::Ready(res) => {
+                reset(&mut this.idle);
+                Poll::Ready(res)
+            }
+            Poll::Pending => {
+                poll_timers(&mut this.idle, &mut this.deadline, cx)?;
+                Poll::Pending
+            }
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.get_mut().body).consume(amt)
+    }
+}
+
+fn reset(timer: &mut Option<(Duration, Delay)>) {
+    if let Some((duration, delay)) = timer {
+        delay.reset(*duration);
+    }
+}
+
+fn poll_timers(
+    idle: &mut Option<(Duration, Delay)>,
+    deadline: &mut Option<(Duration, Delay)>,
+    cx: &mut Context<'_>,
+) -> io::Result<()> {
+    for (kind, timer) in [
+        (TimeoutKind::Total, deadline),
+        (TimeoutKind::ReadIdle, idle),
+    ] {
+        if let Some((duration, delay)) = timer {
+            if Pin::new(delay).poll(cx).is_ready() {
+                return Err(TimeoutError::new(kind, *duration).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::io::AsyncReadExt;
+    use futures_util::{stream, TryStreamExt};
+    use http_types::StatusCode;
+
+    /// A body which sends `chunks`, each after waiting `delay`.
+    fn slow_body(chunks: usize, delay: Duration) -> Body {
+        let chunks = stream::unfold(0, move |sent| async move {
+            if sent == chunks {
+                return None;
+            }
+            Delay::new(delay).await;
+            Some((Ok::<_, io::Error>(vec![b'a']), sent + 1))
+        });
+        Body::from_reader(Box::pin(chunks).into_async_read(), None)
+    }
+
+    async fn send_slow(config: &Config, chunks: usize, delay: Duration) -> io::Result<String> {
+        let mut res = send(config, async move {
+            let mut res = Response::new(StatusCode::Ok);
+            res.set_body(slow_body(chunks, delay));
+            Ok(res)
+        })
+        .await
+        .unwrap();
+
+        let mut body = String::new();
+        res.take_body().read_to_string(&mut body).await?;
+        Ok(body)
+    }
+
+    fn kind(err: &io::Error) -> TimeoutKind {
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        let err = err.get_ref().unwrap().downcast_ref::<ClientError>();
+        let err = err.unwrap().get_ref().downcast_ref::<TimeoutError>();
+        err.unwrap().kind()
+    }
+
+    #[async_std::test]
+    async fn times_out_idle_reads() {
+        let config = Config::new().set_read_idle_timeout(Some(Duration::from_millis(100)));
+        let body = send_slow(&config, 5, Duration::from_millis(20)).await;
+        assert_eq!(body.unwrap(), "aaaaa");
+
+        let err = send_slow(&config, 1, Duration::from_millis(500)).await;
+        assert_eq!(kind(&err.unwrap_err()), TimeoutKind::ReadIdle);
+    }
+
+    #[async_std::test]
+    async fn times_out_whole_requests() {
+        let config = Config::new()
+            .set_read_idle_timeout(Some(Duration::from_millis(100)))
+            .set_total_timeout(Some(Duration::from_millis(150)));
+        let err = send_slow(&config, 10, Duration::from_millis(50)).await;
+        assert_eq!(kind(&err.unwrap_err()), TimeoutKind::Total);
+
+        let err = send(&config, async {
+            Delay::new(Duration::from_millis(500)).await;
+            Ok(Response::new(StatusCode::Ok))
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(TimeoutError::of(&err).unwrap().kind(), TimeoutKind::Total);
+    }
+}
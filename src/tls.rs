@@ -0,0 +1,529 @@
+//! TLS settings which every backend translates into its own TLS stack.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::{self, Debug};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use http_types::{Error, StatusCode};
+
+/// The client identity, root certificates and protocol versions of `https` connections.
+///
+/// Set them with `Config::set_tls`. They are the same on every backend, which each translate
+/// them into their own TLS stack.
+///
+/// # Examples
+///
+/// ```no_run
+/// use http_client::{Certificate, Config, Identity, TlsSettings, TlsVersion};
+///
+/// # fn main() -> std::io::Result<()> {
+/// let tls = TlsSettings::new()
+///     .set_identity(Some(Identity::from_pem_files("client.pem", "client.key")?))
+///     .add_root_certificate(Certificate::from_pem_file("internal-ca.pem")?)
+///     .set_min_version(Some(TlsVersion::Tls1_2));
+/// let config = Config::new().set_tls(tls);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct TlsSettings {
+    pub(crate) identity: Option<Identity>,
+    pub(crate) root_certificates: Vec<Certificate>,
+    pub(crate) built_in_roots: bool,
+    pub(crate) min_version: Option<TlsVersion>,
+    pub(crate) max_version: Option<TlsVersion>,
+}
+
+impl TlsSettings {
+    /// Create new settings, which trust the built-in roots and present no client certificate.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the certificate and private key presented to servers which ask for one.
+    pub fn set_identity(mut self, identity: Option<Identity>) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Trust `certificate`, as well as the built-in roots.
+    ///
+    /// Note: `curl_client` refuses to trust one as well as the built-in roots, so they must be
+    /// disabled with `set_built_in_roots(false)`. It also only supports one root certificate,
+    /// loaded from a file.
+    pub fn add_root_certificate(mut self, certificate: Certificate) -> Self {
+        self.root_certificates.push(certificate);
+        self
+    }
+
+    /// Set whether the built-in roots are trusted: the system's on `native-tls` backends, and
+    /// Mozilla's on `rustls`.
+    ///
+    /// Note: `curl_client` only disables them in favour of a root certificate, and refuses
+    /// settings which trust neither.
+    pub fn set_built_in_roots(mut self, built_in_roots: bool) -> Self {
+        self.built_in_roots = built_in_roots;
+        self
+    }
+
+    /// Set the oldest TLS version which may be negotiated.
+    ///
+    /// Note: `curl_client` refuses settings with a minimum version. `rustls` never negotiates
+    /// versions older than 1.2.
+    pub fn set_min_version(mut self, min_version: Option<TlsVersion>) -> Self {
+        self.min_version = min_version;
+        self
+    }
+
+    /// Set the newest TLS version which may be negotiated.
+    ///
+    /// Note: `curl_client` refuses settings with a maximum version.
+    pub fn set_max_version(mut self, max_version: Option<TlsVersion>) -> Self {
+        self.max_version = max_version;
+        self
+    }
+}
+
+impl Default for TlsSettings {
+    fn default() -> Self {
+        Self {
+            identity: None,
+            root_certificates: Vec::new(),
+            built_in_roots: true,
+            min_version: None,
+            max_version: None,
+        }
+    }
+}
+
+/// A version of the TLS protocol.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TlsVersion {
+    /// TLS 1.0.
+    Tls1_0,
+    /// TLS 1.1.
+    Tls1_1,
+    /// TLS 1.2.
+    Tls1_2,
+    /// TLS 1.3.
+    Tls1_3,
+}
+
+/// A root certificate to trust.
+#[derive(Clone)]
+pub struct Certificate {
+    pub(crate) encoding: Encoding,
+    // Only read by TLS stacks, as `curl_client` only reads the path.
+    #[cfg_attr(
+        not(any(
+            feature = "hyper_client",
+            all(feature = "h1_client", any(feature = "rustls", feature = "native-tls"))
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) data: Vec<u8>,
+    pub(crate) path: Option<PathBuf>,
+}
+
+/// How a certificate or identity is encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Pem,
+    Der,
+}
+
+impl Certificate {
+    /// A certificate in PEM format.
+    pub fn from_pem(pem: &[u8]) -> Self {
+        Self {
+            encoding: Encoding::Pem,
+            data: pem.to_vec(),
+            path: None,
+        }
+    }
+
+    /// A certificate in DER format.
+    pub fn from_der(der: &[u8]) -> Self {
+        Self {
+            encoding: Encoding::Der,
+            data: der.to_vec(),
+            path: None,
+        }
+    }
+
+    /// Read a certificate in PEM format from the file at `path`.
+    pub fn from_pem_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            ..Self::from_pem(&fs::read(path)?)
+        })
+    }
+}
+
+impl Debug for Certificate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Certificate")
+            .field("encoding", &self.encoding)
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+/// A certificate chain and private key, presented to servers which ask for a client certificate.
+#[derive(Clone)]
+pub struct Identity {
+    pub(crate) kind: IdentityKind,
+}
+
+#[derive(Clone)]
+pub(crate) enum IdentityKind {
+    /// A PEM certificate chain, leaf first, and a PEM PKCS #8 private key.
+    // Only read by TLS stacks, as `curl_client` only reads the paths.
+    #[cfg_attr(
+        not(any(
+            feature = "hyper_client",
+            all(feature = "h1_client", any(feature = "rustls", feature = "native-tls"))
+        )),
+        allow(dead_code)
+    )]
+    Pem {
+        certs: Vec<u8>,
+        key: Vec<u8>,
+        paths: Option<(PathBuf, PathBuf)>,
+    },
+    /// A DER PKCS #12 archive, and its password.
+    // Unread when `rustls` is the only TLS stack.
+    #[allow(dead_code)]
+    Pkcs12 {
+        der: Vec<u8>,
+        password: String,
+        path: Option<PathBuf>,
+    },
+}
+
+impl Identity {
+    /// An identity from a certificate chain in PEM format, leaf first, and a private key in PEM
+    /// format.
+    ///
+    /// Note: `native-tls` backends only read PKCS #8 private keys.
+    pub fn from_pem(certs: &[u8], key: &[u8]) -> Self {
+        let kind = IdentityKind::Pem {
+            certs: certs.to_vec(),
+            key: key.to_vec(),
+            paths: None,
+        };
+        Self { kind }
+    }
+
+    /// An identity from a PKCS #12 archive in DER format, encrypted with `password`.
+    ///
+    /// Note: Not supported on `rustls`.
+    pub fn from_pkcs12(der: &[u8], password: &str) -> Self {
+        let kind = IdentityKind::Pkcs12 {
+            der: der.to_vec(),
+            password: password.to_string(),
+            path: None,
+        };
+        Self { kind }
+    }
+
+    /// Read an identity from the certificate chain and private key files at `certs` and `key`,
+    /// both in PEM format.
+    pub fn from_pem_files(certs: impl AsRef<Path>, key: impl AsRef<Path>) -> io::Result<Self> {
+        let (certs, key) = (certs.as_ref(), key.as_ref());
+        let kind = IdentityKind::Pem {
+            certs: fs::read(certs)?,
+            key: fs::read(key)?,
+            paths: Some((certs.to_path_buf(), key.to_path_buf())),
+        };
+        Ok(Self { kind })
+    }
+
+    /// Read an identity from the PKCS #12 archive at `path`, encrypted with `password`.
+    pub fn from_pkcs12_file(path: impl AsRef<Path>, password: &str) -> io::Result<Self> {
+        let path = path.as_ref();
+        let kind = IdentityKind::Pkcs12 {
+            der: fs::read(path)?,
+            password: password.to_string(),
+            path: Some(path.to_path_buf()),
+        };
+        Ok(Self { kind })
+    }
+}
+
+impl Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Leaves out the private key, and the password.
+        match &self.kind {
+            IdentityKind::Pem { paths, .. } => f
+                .debug_struct("Identity")
+                .field("format", &"PEM")
+                .field("paths", paths)
+                .finish(),
+            IdentityKind::Pkcs12 { path, .. } => f
+                .debug_struct("Identity")
+                .field("format", &"PKCS #12")
+                .field("path", path)
+                .finish(),
+        }
+    }
+}
+
+/// The public keys which the certificates of hosts must have, as in HTTP Public Key Pinning.
+///
+/// Each key is pinned as the SHA-256 digest of its DER encoded SubjectPublicKeyInfo. The
+/// certificate a pinned host presents must have one of the keys pinned for it, or the request
+/// fails with `ClientErrorKind::PinnedKeyMismatch`. Hosts without pins are not checked.
+///
+/// # Examples
+///
+/// ```
+/// use http_client::{Config, KeyPins};
+///
+/// let pins = KeyPins::new()
+///     .with_pins("api.example.com", &["sha256/LjBBLTGhVe7XmvZZ72oP4wBKmYsDxZCMMXYS788wIWQ="])?;
+/// let config = Config::new().set_key_pins(Some(pins));
+/// # Ok::<(), http_types::Error>(())
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyPins {
+    hosts: HashMap<String, Vec<[u8; 32]>>,
+}
+
+impl KeyPins {
+    /// Create a new instance, without any pins.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pin `host` to the keys with `digests`, as well as any pinned for it before.
+    pub fn with_host(mut self, host: &str, digests: impl IntoIterator<Item = [u8; 32]>) -> Self {
+        self.hosts
+            .entry(normalize(host))
+            .or_default()
+            .extend(digests);
+        self
+    }
+
+    /// Pin `host` to the keys in `pins`, each `sha256/` followed by the base64 of a digest, as
+    /// `curl --pinnedpubkey` takes them.
+    pub fn with_pins(
+        self,
+        host: &str,
+        pins: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> http_types::Result<Self> {
+        let digests = pins
+            .into_iter()
+            .map(|pin| {
+                let pin = pin.as_ref();
+                let invalid = || {
+                    let msg = format!("invalid key pin: {}", pin);
+                    Error::from_str(StatusCode::BadRequest, msg)
+                };
+                let digest = pin.strip_prefix("sha256/").ok_or_else(invalid)?;
+                let digest = base64::decode(digest).map_err(|_| invalid())?;
+                <[u8; 32]>::try_from(digest.as_slice()).map_err(|_| invalid())
+            })
+            .collect::<http_types::Result<Vec<_>>>()?;
+        Ok(self.with_host(host, digests))
+    }
+}
+
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// What was negotiated in the TLS handshake of the connection a response came over.
+///
+/// `h1_client` and `hyper_client` insert this into the extensions of every response to an
+/// `https` request.
+///
+/// Note: `native-tls` does not tell which version or cipher suite was negotiated, nor the
+/// certificates behind the one of the server, so only `h1_client` with `rustls` knows them.
+///
+/// # Examples
+///
+/// ```no_run
+/// use http_client::{HttpClient, Request, TlsInfo};
+/// # async fn example(client: impl HttpClient) -> Result<(), http_types::Error> {
+/// let res = client.send(Request::get("https://example.com")).await?;
+/// if let Some(info) = res.ext().get::<TlsInfo>() {
+///     println!("negotiated {:?} with {:?}", info.version(), info.cipher());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TlsInfo {
+    pub(crate) version: Option<TlsVersion>,
+    pub(crate) cipher: Option<String>,
+    pub(crate) alpn_protocol: Option<Vec<u8>>,
+    pub(crate) peer_certificates: Vec<Vec<u8>>,
+}
+
+impl TlsInfo {
+    /// The version of the protocol, when the TLS stack tells.
+    pub fn version(&self) -> Option<TlsVersion> {
+        self.version
+    }
+
+    /// The IANA name of the cipher suite, such as `TLS13_AES_128_GCM_SHA256`, when the TLS stack
+    /// tells.
+    pub fn cipher(&self) -> Option<&str> {
+        self.cipher.as_deref()
+    }
+
+    /// The protocol agreed on with ALPN, if any.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_deref()
+    }
+
+    /// The DER encoded certificates the server presented, starting with its own.
+    pub fn peer_certificates(&self) -> &[Vec<u8>] {
+        &self.peer_certificates
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(any(
+        feature = "hyper_client",
+        all(feature = "h1_client", any(feature = "rustls", feature = "native-tls"))
+    ))] {
+        use sha2::{Digest, Sha256};
+
+        use crate::{ClientError, ClientErrorKind};
+
+        impl KeyPins {
+            /// Check that `cert`, the DER encoded certificate `host` presented, has one of the
+            /// keys pinned for `host`.
+            pub(crate) fn check(&self, host: &str, cert: Option<&[u8]>) -> Result<(), ClientError> {
+                let digests = match self.hosts.get(&normalize(host)) {
+                    Some(digests) => digests,
+                    None => return Ok(()),
+                };
+                let pinned = match cert.and_then(spki) {
+                    Some(spki) => {
+                        let digest = Sha256::digest(spki);
+                        digests.iter().any(|pin| pin[..] == digest[..])
+                    }
+                    None => false,
+                };
+                if pinned {
+                    return Ok(());
+                }
+                let msg = format!("the certificate of {} has none of its pinned keys", host);
+                Err(ClientError::new(ClientErrorKind::PinnedKeyMismatch, msg))
+            }
+        }
+
+        /// The DER encoded SubjectPublicKeyInfo of the DER encoded X.509 certificate `cert`.
+        fn spki(cert: &[u8]) -> Option<&[u8]> {
+            const SEQUENCE: u8 = 0x30;
+            const VERSION: u8 = 0xa0;
+
+            let (cert, _) = der_element(cert)?;
+            let (tbs, _) = der_element(cert.content)?;
+            // The version is optional, and the serial number follows it.
+            let (mut field, mut rest) = der_element(tbs.content)?;
+            if field.tag == VERSION {
+                let (next, after) = der_element(rest)?;
+                field = next;
+                rest = after;
+            }
+            // Past the signature algorithm, issuer, validity and subject.
+            for _ in 0..5 {
+                let (next, after) = der_element(rest)?;
+                field = next;
+                rest = after;
+            }
+            Some(field.all).filter(|_| field.tag == SEQUENCE)
+        }
+
+        /// A DER element: its tag, all of it, and its content.
+        struct DerElement<'a> {
+            tag: u8,
+            all: &'a [u8],
+            content: &'a [u8],
+        }
+
+        /// The DER element at the start of `data`, and the rest of `data`.
+        fn der_element(data: &[u8]) -> Option<(DerElement<'_>, &[u8])> {
+            let tag = *data.first()?;
+            let len = *data.get(1)?;
+            let (header, len) = if len < 0x80 {
+                (2, usize::from(len))
+            } else {
+                // Long form lengths give the number of bytes the length is written in.
+                let n = usize::from(len & 0x7f);
+                if n == 0 || n > 4 {
+                    return None;
+                }
+                let bytes = data.get(2..2 + n)?;
+                let len = bytes
+                    .iter()
+                    .fold(0, |len, byte| len << 8 | usize::from(*byte));
+                (2 + n, len)
+            };
+            let end = header.checked_add(len)?;
+            let all = data.get(..end)?;
+            let element = DerElement {
+                tag,
+                all,
+                content: &all[header..],
+            };
+            Some((element, &data[end..]))
+        }
+    }
+}
+
+/// A `native-tls` connector builder with `settings`.
+#[cfg(any(
+    feature = "hyper_client",
+    all(feature = "h1_client", feature = "native-tls", not(feature = "rustls"))
+))]
+pub(crate) fn native_tls_builder(
+    settings: &TlsSettings,
+) -> native_tls_crate::Result<native_tls_crate::TlsConnectorBuilder> {
+    use native_tls_crate::Protocol;
+
+    let protocol = |version| match version {
+        TlsVersion::Tls1_0 => Protocol::Tlsv10,
+        TlsVersion::Tls1_1 => Protocol::Tlsv11,
+        TlsVersion::Tls1_2 => Protocol::Tlsv12,
+        TlsVersion::Tls1_3 => Protocol::Tlsv13,
+    };
+
+    let mut builder = native_tls_crate::TlsConnector::builder();
+    match &settings.identity {
+        Some(Identity {
+            kind: IdentityKind::Pem { certs, key, .. },
+        }) => {
+            builder.identity(native_tls_crate::Identity::from_pkcs8(certs, key)?);
+        }
+        Some(Identity {
+            kind: IdentityKind::Pkcs12 { der, password, .. },
+        }) => {
+            builder.identity(native_tls_crate::Identity::from_pkcs12(der, password)?);
+        }
+        None => {}
+    }
+    for certificate in &settings.root_certificates {
+        let certificate = match certificate.encoding {
+            Encoding::Pem => native_tls_crate::Certificate::from_pem(&certificate.data)?,
+            Encoding::Der => native_tls_crate::Certificate::from_der(&certificate.data)?,
+        };
+        builder.add_root_certificate(certificate);
+    }
+    builder.disable_built_in_roots(!settings.built_in_roots);
+    // `None` would allow versions older than TLS 1.0, so the default of `native-tls` is kept.
+    if let Some(version) = settings.min_version {
+        builder.min_protocol_version(Some(protocol(version)));
+    }
+    builder.max_protocol_version(settings.max_version.map(protocol));
+    Ok(builder)
+}
@@ -6,11 +6,15 @@ use std::pin::Pin;
 use futures::prelude::*;
 use send_wrapper::SendWrapper;
 
-use crate::Config;
+use crate::error::{body_read_errors, classify};
+use crate::timeout::{self, timeout};
+use crate::{ClientErrorKind, Config, TimeoutKind};
 
-use super::{http_types::Headers, Body, Error, HttpClient, Request, Response};
+use super::{http_types::Headers, Error, HttpClient, Request, Response};
 
 /// WebAssembly HTTP Client.
+///
+/// Sends requests with `fetch`, in browsers, web workers, Node.js 18+ and Deno.
 #[derive(Debug)]
 pub struct WasmClient {
     config: Config,
@@ -43,24 +47,16 @@ impl HttpClient for WasmClient {
         let config = self.config.clone();
 
         wrap_send(async move {
-            let req: fetch::Request = fetch::Request::new(req).await?;
-            let conn = req.send();
-            let mut res = if let Some(timeout) = config.timeout {
-                async_std::future::timeout(timeout, conn).await??
-            } else {
-                conn.await?
+            let send = async {
+                let req: fetch::Request = fetch::Request::new(req, &config.fetch).await?;
+                let duration = config.response_header_timeout.or(config.timeout);
+                let res = timeout(TimeoutKind::ResponseHeader, duration, req.send()).await??;
+                Ok::<_, Error>(into_response(res))
             };
-
-            let body = res.body_bytes();
-            let mut response =
-                Response::new(http_types::StatusCode::try_from(res.status()).unwrap());
-            response.set_body(Body::from(body));
-            for (name, value) in res.headers() {
-                let name: http_types::headers::HeaderName = name.parse().unwrap();
-                response.append_header(&name, value);
-            }
-
-            Ok(response)
+            let res = timeout::send(&config, send)
+                .await
+                .map_err(|e| classify(e, ClientErrorKind::Other))?;
+            Ok(body_read_errors(res))
         })
     }
 
@@ -79,6 +75,18 @@ impl HttpClient for WasmClient {
     }
 }
 
+fn into_response(mut res: fetch::Response) -> Response {
+    let body = res.take_body();
+    let mut response = Response::new(http_types::StatusCode::try_from(res.status()).unwrap());
+    response.set_body(body);
+    for (name, value) in res.headers() {
+        let name: http_types::headers::HeaderName = name.parse().unwrap();
+        response.append_header(&name, value);
+    }
+    response
+}
+
+#[allow(clippy::infallible_try_from)]
 impl TryFrom<Config> for WasmClient {
     type Error = Infallible;
 
@@ -97,25 +105,39 @@ where
 }
 
 mod fetch {
-    use js_sys::{Array, ArrayBuffer, Reflect, Uint8Array};
+    use futures::io::{AsyncRead, BufReader};
+    use futures::ready;
+    use js_sys::{Array, Function, Promise, Reflect, Uint8Array};
+    use send_wrapper::SendWrapper;
     use wasm_bindgen::{prelude::*, JsCast};
     use wasm_bindgen_futures::JsFuture;
-    use web_sys::{RequestInit, Window, WorkerGlobalScope};
+    use web_sys::{
+        AbortController, ReadableStreamDefaultReader, RequestInit, Window, WorkerGlobalScope,
+    };
 
+    use std::future::Future;
+    use std::io;
     use std::iter::{IntoIterator, Iterator};
     use std::pin::Pin;
+    use std::task::{Context, Poll};
 
     use http_types::StatusCode;
 
-    use crate::Error;
+    use crate::{
+        Body, ClientError, ClientErrorKind, Error, FetchCache, FetchCredentials, FetchMode,
+        FetchOptions, FetchRedirect, ReferrerPolicy,
+    };
 
-    enum WindowOrWorker {
+    /// The global scope which fetches requests.
+    enum Scope {
         Window(Window),
         Worker(WorkerGlobalScope),
+        /// Any other scope with a global `fetch`, such as those of Node.js 18+ and Deno.
+        Global(Function),
     }
 
-    impl WindowOrWorker {
-        fn new() -> Self {
+    impl Scope {
+        fn new() -> Result<Self, Error> {
             #[wasm_bindgen]
             extern "C" {
                 type Global;
@@ -130,11 +152,31 @@ mod fetch {
             let global: Global = js_sys::global().unchecked_into();
 
             if !global.window().is_undefined() {
-                Self::Window(global.unchecked_into())
+                Ok(Self::Window(global.unchecked_into()))
             } else if !global.worker().is_undefined() {
-                Self::Worker(global.unchecked_into())
+                Ok(Self::Worker(global.unchecked_into()))
             } else {
-                panic!("Only supported in a browser or web worker");
+                Reflect::get(&global, &JsValue::from_str("fetch"))
+                    .ok()
+                    .and_then(|fetch| fetch.dyn_into::<Function>().ok())
+                    .map(Self::Global)
+                    .ok_or_else(|| {
+                        let message = "no `fetch` function in this JavaScript environment";
+                        ClientError::new(ClientErrorKind::Other, message).into_error()
+                    })
+            }
+        }
+
+        /// Start fetching `request`.
+        fn fetch(&self, request: &web_sys::Request) -> Promise {
+            match self {
+                Scope::Window(window) => window.fetch_with_request(request),
+                Scope::Worker(worker) => worker.fetch_with_request(request),
+                // A `fetch` which throws is treated as one which rejects.
+                Scope::Global(fetch) => match fetch.call1(&js_sys::global(), request) {
+                    Ok(value) => Promise::resolve(&value),
+                    Err(e) => Promise::reject(&e),
+                },
             }
         }
     }
@@ -143,21 +185,40 @@ mod fetch {
 
     /// An HTTP Fetch Request.
     pub(crate) struct Request {
+        scope: Scope,
         request: web_sys::Request,
         /// This field stores the body of the request to ensure it stays allocated as long as the request needs it.
         #[allow(dead_code)]
         body_buf: Pin<Vec<u8>>,
+        abort: AbortOnDrop,
     }
 
     impl Request {
         /// Create a new instance.
-        pub(crate) async fn new(mut req: super::Request) -> Result<Self, Error> {
+        pub(crate) async fn new(
+            mut req: super::Request,
+            options: &FetchOptions,
+        ) -> Result<Self, Error> {
+            // find the fetch function before anything else the environment may lack
+            let scope = Scope::new()?;
+
             // create a fetch request initaliser
             let mut init = RequestInit::new();
 
             // set the fetch method
             init.method(req.method().as_ref());
 
+            // set the fetch options of the request, or else those of the config
+            let options = req.ext().get::<FetchOptions>().unwrap_or(options);
+            set_options(&mut init, options);
+
+            // abort the fetch when it is no longer wanted, such as when a timeout elapses
+            let controller = AbortController::new().map_err(|e| {
+                let message = format!("failed to create abort controller: {:?}", e);
+                ClientError::new(ClientErrorKind::Other, message).into_error()
+            })?;
+            init.signal(Some(&controller.signal()));
+
             let uri = req.url().to_string();
             let body = req.take_body();
 
@@ -165,9 +226,10 @@ mod fetch {
             // needs to be pinned and retained inside the Request because the Uint8Array passed to
             // js is just a portal into WASM linear memory, and if the underlying data is moved the
             // js ref will become silently invalid
-            let body_buf = body.into_bytes().await.map_err(|_| {
-                Error::from_str(StatusCode::BadRequest, "could not read body into a buffer")
-            })?;
+            let body_buf = body
+                .into_bytes()
+                .await
+                .map_err(|e| ClientError::new(ClientErrorKind::Io, e.into_inner()).into_error())?;
             let body_pinned = Pin::new(body_buf);
             if body_pinned.len() > 0 {
                 let uint_8_array = unsafe { js_sys::Uint8Array::view(&body_pinned) };
@@ -175,10 +237,8 @@ mod fetch {
             }
 
             let request = web_sys::Request::new_with_str_and_init(&uri, &init).map_err(|e| {
-                Error::from_str(
-                    StatusCode::BadRequest,
-                    format!("failed to create request: {:?}", e),
-                )
+                let message = format!("failed to create request: {:?}", e);
+                ClientError::new(ClientErrorKind::InvalidUrl, message).into_error()
             })?;
 
             // add any fetch headers
@@ -196,48 +256,135 @@ mod fetch {
             }
 
             Ok(Self {
+                scope,
                 request,
                 body_buf: body_pinned,
+                abort: AbortOnDrop(Some(controller)),
             })
         }
 
         /// Submit a request
+        ///
+        /// Dropping the returned future before it completes aborts the fetch.
         // TODO(yoshuawuyts): turn this into a `Future` impl on `Request` instead.
-        pub(crate) async fn send(self) -> Result<Response, Error> {
+        pub(crate) async fn send(mut self) -> Result<Response, Error> {
             // Send the request.
-            let scope = WindowOrWorker::new();
-            let promise = match scope {
-                WindowOrWorker::Window(window) => window.fetch_with_request(&self.request),
-                WindowOrWorker::Worker(worker) => worker.fetch_with_request(&self.request),
-            };
-            let resp = JsFuture::from(promise)
-                .await
-                .map_err(|e| Error::from_str(StatusCode::BadRequest, format!("{:?}", e)))?;
+            let promise = self.scope.fetch(&self.request);
+            // fetch rejects with a `TypeError` when the request could not be made at all, and
+            // with an `AbortError` when it was aborted.
+            let resp = JsFuture::from(promise).await.map_err(|e| {
+                let name = Reflect::get(&e, &JsValue::from_str("name")).ok();
+                let kind = match name.and_then(|name| name.as_string()) {
+                    Some(name) if name == "AbortError" => ClientErrorKind::Cancelled,
+                    _ => ClientErrorKind::Connect,
+                };
+                ClientError::new(kind, format!("{:?}", e)).into_error()
+            })?;
+            // The response has arrived, and its body is cancelled when dropped instead.
+            self.abort.0 = None;
 
             debug_assert!(resp.is_instance_of::<web_sys::Response>());
             let res: web_sys::Response = resp.dyn_into().unwrap();
 
-            // Get the response body.
-            let promise = res.array_buffer().unwrap();
-            let resp = JsFuture::from(promise).await.unwrap();
-            debug_assert!(resp.is_instance_of::<js_sys::ArrayBuffer>());
-            let buf: ArrayBuffer = resp.dyn_into().unwrap();
-            let slice = Uint8Array::new(&buf);
-            let mut body: Vec<u8> = vec![0; slice.length() as usize];
-            slice.copy_to(&mut body);
+            Ok(Response::new(res))
+        }
+    }
+
+    /// Set `options` on `init`.
+    fn set_options(init: &mut RequestInit, options: &FetchOptions) {
+        if let Some(mode) = options.mode {
+            init.mode(match mode {
+                FetchMode::Cors => web_sys::RequestMode::Cors,
+                FetchMode::NoCors => web_sys::RequestMode::NoCors,
+                FetchMode::SameOrigin => web_sys::RequestMode::SameOrigin,
+                FetchMode::Navigate => web_sys::RequestMode::Navigate,
+            });
+        }
+        if let Some(credentials) = options.credentials {
+            init.credentials(match credentials {
+                FetchCredentials::Omit => web_sys::RequestCredentials::Omit,
+                FetchCredentials::SameOrigin => web_sys::RequestCredentials::SameOrigin,
+                FetchCredentials::Include => web_sys::RequestCredentials::Include,
+            });
+        }
+        if let Some(cache) = options.cache {
+            init.cache(match cache {
+                FetchCache::Default => web_sys::RequestCache::Default,
+                FetchCache::NoStore => web_sys::RequestCache::NoStore,
+                FetchCache::Reload => web_sys::RequestCache::Reload,
+                FetchCache::NoCache => web_sys::RequestCache::NoCache,
+                FetchCache::ForceCache => web_sys::RequestCache::ForceCache,
+                FetchCache::OnlyIfCached => web_sys::RequestCache::OnlyIfCached,
+            });
+        }
+        if let Some(redirect) = options.redirect {
+            init.redirect(match redirect {
+                FetchRedirect::Follow => web_sys::RequestRedirect::Follow,
+                FetchRedirect::Error => web_sys::RequestRedirect::Error,
+                FetchRedirect::Manual => web_sys::RequestRedirect::Manual,
+            });
+        }
+        if let Some(referrer) = &options.referrer {
+            init.referrer(referrer);
+        }
+        if let Some(policy) = options.referrer_policy {
+            init.referrer_policy(match policy {
+                ReferrerPolicy::NoReferrer => web_sys::ReferrerPolicy::NoReferrer,
+                ReferrerPolicy::NoReferrerWhenDowngrade => {
+                    web_sys::ReferrerPolicy::NoReferrerWhenDowngrade
+                }
+                ReferrerPolicy::Origin => web_sys::ReferrerPolicy::Origin,
+                ReferrerPolicy::OriginWhenCrossOrigin => {
+                    web_sys::ReferrerPolicy::OriginWhenCrossOrigin
+                }
+                ReferrerPolicy::SameOrigin => web_sys::ReferrerPolicy::SameOrigin,
+                ReferrerPolicy::StrictOrigin => web_sys::ReferrerPolicy::StrictOrigin,
+                ReferrerPolicy::StrictOriginWhenCrossOrigin => {
+                    web_sys::ReferrerPolicy::StrictOriginWhenCrossOrigin
+                }
+                ReferrerPolicy::UnsafeUrl => web_sys::ReferrerPolicy::UnsafeUrl,
+            });
+        }
+        if let Some(integrity) = &options.integrity {
+            init.integrity(integrity);
+        }
+        if options.keepalive {
+            // Older versions of web-sys have no setter for `keepalive`.
+            let _ = Reflect::set(
+                init.as_ref(),
+                &JsValue::from_str("keepalive"),
+                &JsValue::TRUE,
+            );
+        }
+    }
+
+    /// Aborts the fetch of its controller when dropped.
+    struct AbortOnDrop(Option<AbortController>);
 
-            Ok(Response::new(res, body))
+    impl Drop for AbortOnDrop {
+        fn drop(&mut self) {
+            if let Some(controller) = self.0.take() {
+                controller.abort();
+            }
         }
     }
 
     /// An HTTP Fetch Response.
     pub(crate) struct Response {
         res: web_sys::Response,
-        body: Option<Vec<u8>>,
+        body: Option<Body>,
     }
 
     impl Response {
-        fn new(res: web_sys::Response, body: Vec<u8>) -> Self {
+        fn new(res: web_sys::Response) -> Self {
+            // The body is read as it arrives, from the stream of it the response holds.
+            // Responses without one, such as those to `HEAD` requests, have an empty body.
+            let body = match res.body() {
+                // The browser decodes compressed bodies, so `Content-Length` need not be the
+                // length of this one.
+                Some(stream) => Body::from_reader(BufReader::new(BodyReader::new(&stream)), None),
+                None => Body::empty(),
+            };
             Self {
                 res,
                 body: Some(body),
@@ -251,11 +398,11 @@ mod fetch {
             }
         }
 
-        /// Get the request body as a byte vector.
+        /// Take the response body, which streams from the network.
         ///
-        /// Returns an empty vector if the body has already been consumed.
-        pub(crate) fn body_bytes(&mut self) -> Vec<u8> {
-            self.body.take().unwrap_or_else(|| vec![])
+        /// Returns an empty body if it has already been taken.
+        pub(crate) fn take_body(&mut self) -> Body {
+            self.body.take().unwrap_or_else(Body::empty)
         }
 
         /// Get the HTTP return status code.
@@ -264,6 +411,91 @@ mod fetch {
         }
     }
 
+    /// Reads a response body from its `ReadableStream`, one chunk at a time.
+    ///
+    /// JS values never leave the thread they were created on, which is the only one there is.
+    struct BodyReader(SendWrapper<ChunkReader>);
+
+    struct ChunkReader {
+        reader: ReadableStreamDefaultReader,
+        /// The pending read of the next chunk.
+        read: Option<JsFuture>,
+        chunk: Vec<u8>,
+        pos: usize,
+        done: bool,
+    }
+
+    impl BodyReader {
+        fn new(stream: &web_sys::ReadableStream) -> Self {
+            Self(SendWrapper::new(ChunkReader {
+                reader: stream.get_reader().unchecked_into(),
+                read: None,
+                chunk: Vec::new(),
+                pos: 0,
+                done: false,
+            }))
+        }
+    }
+
+    impl AsyncRead for BodyReader {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = &mut *self.0;
+            loop {
+                if this.pos < this.chunk.len() {
+                    let n = buf.len().min(this.chunk.len() - this.pos);
+                    buf[..n].copy_from_slice(&this.chunk[this.pos..this.pos + n]);
+                    this.pos += n;
+                    return Poll::Ready(Ok(n));
+                }
+                if this.done {
+                    return Poll::Ready(Ok(0));
+                }
+
+                let reader = &this.reader;
+                let read = this
+                    .read
+                    .get_or_insert_with(|| JsFuture::from(reader.read()));
+                let result = ready!(Pin::new(read).poll(cx));
+                this.read = None;
+                // `io::Error::other` needs a newer Rust than this crate supports.
+                #[allow(clippy::io_other_error)]
+                let result =
+                    result.map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?;
+
+                // Each read resolves to `{ done, value }`, where `value` is a `Uint8Array`.
+                let done = Reflect::get(&result, &JsValue::from_str("done"))
+                    .ok()
+                    .and_then(|done| done.as_bool())
+                    .unwrap_or(true);
+                if done {
+                    this.done = true;
+                    continue;
+                }
+                let value = Reflect::get(&result, &JsValue::from_str("value"))
+                    .ok()
+                    .and_then(|value| value.dyn_into::<Uint8Array>().ok())
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, "body chunk is not bytes")
+                    })?;
+                this.chunk = value.to_vec();
+                this.pos = 0;
+            }
+        }
+    }
+
+    impl Drop for ChunkReader {
+        fn drop(&mut self) {
+            // Stop downloading a body which is no longer wanted.
+            if !self.done {
+                let _ = self.reader.cancel();
+            }
+        }
+    }
+
     /// HTTP Headers.
     pub(crate) struct Headers {
         headers: web_sys::Headers,
@@ -18,7 +18,7 @@ cfg_if! {
         use http_client::isahc::IsahcClient as DefaultClient;
     } else if #[cfg(feature = "wasm_client")] {
         use http_client::wasm::WasmClient as DefaultClient;
-    } else if #[cfg(any(feature = "h1_client", feature = "h1_client_rustls"))] {
+    } else if #[cfg(feature = "h1_client")] {
         use http_client::h1::H1Client as DefaultClient;
     } else if #[cfg(feature = "hyper_client")] {
         use http_client::hyper::HyperClient as DefaultClient;